@@ -21,16 +21,37 @@ SOFTWARE. */
 
 use rand::{
     Rng,
-    thread_rng,
+    RngCore,
     prelude::SliceRandom,
 };
 use std::collections::HashSet;
+use crate::{
+    Error,
+    Result,
+};
 
 pub mod obfuscator;
 pub mod deobfuscator;
+pub mod arithmetic;
+pub mod diagnostic;
+pub mod emulator;
+pub mod encoding;
+pub mod lexer;
+pub mod loops;
+pub mod normalize;
+pub mod report;
+pub mod verify;
+
+
+/// The longest command line cmd.exe will accept, in characters.
+pub const MAX_LINE_LENGTH: usize = 8191;
 
+/// The number of random names tried before *generate_random_chars* gives up on finding an unused one.
+pub const MAX_NAME_ATTEMPTS: usize = 10_000;
 
-#[derive(Debug)]
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CharSet {
     /// A character set equivalent to Python's `string.ascii_letters + string.digits + string.punctuation`.
     FullSet,
@@ -40,6 +61,8 @@ pub enum CharSet {
     BadChars,
     /// A character set containing chars which **should** be safe to use in obfuscated variable names.
     GoodChars,
+    /// A character set equivalent to *GoodChars* without `!`, which delayed expansion would read as the end of a name.
+    DelayedSafe,
 }
 
 impl CharSet {
@@ -63,6 +86,19 @@ impl CharSet {
                             'v','w','x','y','z','A','B','C','D','E','F','G','H','I','J','K','L','M','N','O','P','Q',
                             'R','S','T','U','V','W','X','Y','Z','0','1','2','3','4','5','6','7','8','9','!','#',
                             '$','*','(',')','[',']','{','}',',','-','.','?','@','_','~'],
+
+            CharSet::DelayedSafe => vec!['a','b','c','d','e','f','g','h','i','j','k','l','m','n','o','p','q','r','s','t','u',
+                            'v','w','x','y','z','A','B','C','D','E','F','G','H','I','J','K','L','M','N','O','P','Q',
+                            'R','S','T','U','V','W','X','Y','Z','0','1','2','3','4','5','6','7','8','9','#',
+                            '$','*','(',')','[',']','{','}',',','-','.','?','@','_','~'],
+        }
+    }
+
+    /// Returns the character set to draw names from in place of this one while delayed expansion is enabled, which leaves out `!`.
+    pub fn without_bang(&self) -> CharSet {
+        match *self {
+            CharSet::FullSet | CharSet::GoodChars | CharSet::DelayedSafe => CharSet::DelayedSafe,
+            CharSet::Letters | CharSet::BadChars => CharSet::Letters,
         }
     }
 }
@@ -71,35 +107,68 @@ impl CharSet {
 /* Batch Utility Functions */
 
 /// Returns a string of a random length between min/max containing random letters (mixed case), digits and some symbols.<br><br>
+/// The first and last characters are always letters, while the rest are drawn from *charset*.<br>
+/// Characters of *charset* which are not safe in variable names are left out, falling back to letters if none remain.<br>
+/// Names already present in *used* are never returned. Like cmd, the comparison ignores case, so *used* should hold lowercase names.<br><br>
+/// Randomness is drawn from *rng*, so a seeded generator always produces the same sequence of names.<br><br>
 /// Call with *min* or *max* set to *None* to use default values.<br>
 /// Min default value is (7), Max default value is (109).<br><br>
-/// Batch has a single-line limit of **8191**, so keep this in mind when changing these values.<br><br>
+/// Batch has a single-line limit of **8191** (see *MAX_LINE_LENGTH*), so keep this in mind when changing these values.<br><br>
 /// Shorter commands can use larger values to generate more noise.<br>
-/// Longer commands run the risk of breaking in the terminal if the obfuscated length exceeds the limit.
-pub fn generate_random_chars(min: Option<u32>, max: Option<u32>, used: &HashSet<String>) -> String {
+/// Longer commands run the risk of breaking in the terminal if the obfuscated length exceeds the limit.<br><br>
+/// Returns **Error::NamesExhausted** if no unused name turns up within *MAX_NAME_ATTEMPTS* tries.
+pub fn generate_random_chars<R: RngCore + ?Sized>(min: Option<u32>, max: Option<u32>, charset: &CharSet, used: &HashSet<String>, rng: &mut R) -> Result<String> {
     // Functionally-default values for min and max lengths.
-    let min_len: u32 = min.unwrap_or(7);
-    let max_len: u32 = max.unwrap_or(109);
-
-    let mut rng_chars: Vec<char> = Vec::new();
-    let mut rng = thread_rng();
-
-    // Numbers and *most* symbols can be included in variable names as long as they aren't directly adjacent to the leading or trailing '%'.
-    #[allow(non_snake_case)]
-    for N in 0..(thread_rng().gen_range(min_len..=max_len)-1) {
-        if N == 0 {
-            rng_chars.push(*CharSet::Letters.values().choose(&mut rng).expect("CharSet::Letters should not be empty!"));
-        }else {
-            rng_chars.push(*CharSet::GoodChars.values().choose(&mut rng).expect("CharSet::GoodChars should not be empty!"));
+    let min_len: u32 = min.unwrap_or(7).max(1);
+    let max_len: u32 = max.unwrap_or(109).max(min_len);
+    let body: Vec<char> = name_chars(charset);
+
+    for _ in 0..MAX_NAME_ATTEMPTS {
+        let mut rng_chars: Vec<char> = Vec::new();
+
+        // Numbers and *most* symbols can be included in variable names as long as they aren't directly adjacent to the leading or trailing '%'.
+        #[allow(non_snake_case)]
+        for N in 0..(rng.gen_range(min_len..=max_len)-1) {
+            if N == 0 {
+                rng_chars.push(*CharSet::Letters.values().choose(rng).expect("CharSet::Letters should not be empty!"));
+            }else {
+                rng_chars.push(*body.choose(rng).expect("CharSet should not be empty!"));
+            };
+        };
+        rng_chars.push(*CharSet::Letters.values().choose(rng).expect("Charset::Letters should not be empty!"));
+
+        let rng_string: String = rng_chars.into_iter().collect();
+        if !used.contains(&rng_string.to_lowercase()) {
+            return Ok(rng_string);
         };
     };
-    rng_chars.push(*CharSet::Letters.values().choose(&mut rng).expect("Charset::Letters should not be empty!"));
 
-    let rng_string: String = rng_chars.into_iter().collect();
+    Err(Error::NamesExhausted)
+}
 
-    if !used.contains(&rng_string) {
-        rng_string
-    }else {
-        generate_random_chars(Some(min_len), Some(max_len), used)
-    }
-}
\ No newline at end of file
+/// Returns the number of distinct names *generate_random_chars* can produce for the given lengths and charset, ignoring case as cmd does.<br>
+/// The count saturates at *u64::MAX*.
+pub fn name_space(min: u32, max: u32, charset: &CharSet) -> u64 {
+    let letters: u64 = 26;
+    let body: u64 = name_chars(charset).iter()
+        .map(|c| c.to_ascii_lowercase())
+        .collect::<HashSet<char>>()
+        .len() as u64;
+    let min_len: u32 = min.max(1);
+
+    (min_len..=max.max(min_len)).fold(0, |total: u64, len: u32| {
+        let names: u64 = match len {
+            1 => letters,
+            _ => (letters * letters).saturating_mul(body.saturating_pow(len - 2)),
+        };
+        total.saturating_add(names)
+    })
+}
+
+/// Returns the characters of *charset* which are safe in the body of a variable name.
+fn name_chars(charset: &CharSet) -> Vec<char> {
+    let safe: Vec<char> = CharSet::GoodChars.values();
+    let chars: Vec<char> = charset.values().into_iter().filter(|c| safe.contains(c)).collect();
+
+    if chars.is_empty() { CharSet::Letters.values() } else { chars }
+}
@@ -0,0 +1,382 @@
+/* Copyright (c) 2022 Zach Griffin (0xTas)
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE. */
+
+
+
+use std::collections::HashMap;
+use crate::batch::{
+    emulator::Environment,
+    MAX_LINE_LENGTH,
+};
+
+
+/// Operators made of more than one character, which are matched before the single-character ones.
+const LONG_OPERATORS: [&str; 12] = ["<<=", ">>=", "<<", ">>", "*=", "/=", "%=", "+=", "-=", "&=", "^=", "|="];
+
+/// Characters which are operators on their own, and so can never be part of a variable name.
+const OPERATOR_CHARS: &str = "+-*/%()!~<>&^|=,";
+
+/// How deeply parentheses, unary operators and assignments may nest before an expression is given up on as unresolved.
+pub const MAX_DEPTH: usize = 256;
+
+
+/// The result of evaluating a `set /a` expression.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Evaluation {
+    /// The value of the last expression in the comma-separated list.
+    pub value: i32,
+    /// Every assignment made, in order, as a (name, value) pair.
+    pub assignments: Vec<(String, i32)>,
+    /// Variables from the environment which were read by name, in the order they were first read.
+    pub references: Vec<String>,
+}
+
+
+/// ### Evaluates a `set /a` expression the way cmd does, using 32-bit integers which wrap on overflow.<br>
+/// Every operator cmd supports is recognised, from `!`, `~` and unary `-` down to `,`, along with parentheses and compound assignments such as `<<=`.<br>
+/// Literals may be decimal, hexadecimal with a `0x` prefix or octal with a leading `0`, and bare names read variables from *environment*, counting as zero when undefined or not a number.<br><br>
+/// *expression* should already be through percent expansion, with quotes and caret escapes removed.
+/// Returns *None* wherever cmd would print an error instead, such as for a malformed number, a division by zero or a missing operand,
+/// and also for expressions longer than *MAX_LINE_LENGTH* or nested deeper than *MAX_DEPTH*.
+/// ### Example Usage:<br>
+/// ```
+/// use oxidizer::batch::{
+///     arithmetic::evaluate,
+///     emulator::Environment,
+/// };
+/// 
+/// let mut environment = Environment::new();
+/// environment.set("base", "0x60");
+/// 
+/// let evaluation = evaluate("c=base+020, c|=1", &environment).unwrap();
+/// 
+/// assert_eq!(evaluation.value, 113);
+/// assert_eq!(evaluation.assignments, [("c".to_string(), 112), ("c".to_string(), 113)]);
+/// assert!(evaluate("1/0", &environment).is_none());
+/// ```
+pub fn evaluate(expression: &str, environment: &Environment) -> Option<Evaluation> {
+    if expression.chars().count() > MAX_LINE_LENGTH {
+        return None;
+    };
+
+    let mut parser: Parser = Parser {
+        tokens: lex(expression)?,
+        pos: 0,
+        depth: 0,
+        environment,
+        assigned: HashMap::new(),
+        evaluation: Evaluation::default(),
+    };
+
+    parser.evaluation.value = parser.list()?;
+    if parser.pos < parser.tokens.len() {
+        return None;
+    };
+
+    Some(parser.evaluation)
+}
+
+
+/// A single token of an arithmetic expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Atom {
+    Number(i32),
+    Name(String),
+    Operator(&'static str),
+}
+
+/// Splits an expression into numbers, names and operators, returning *None* if a number is malformed.
+fn lex(expression: &str) -> Option<Vec<Atom>> {
+    let mut atoms: Vec<Atom> = Vec::new();
+    let mut rest: &str = expression;
+
+    loop {
+        rest = rest.trim_start();
+        let c: char = match rest.chars().next() {
+            Some(c) => c,
+            None => return Some(atoms),
+        };
+
+        let len: usize = if let Some(operator) = LONG_OPERATORS.iter().find(|operator| rest.starts_with(**operator)) {
+            atoms.push(Atom::Operator(operator));
+            operator.len()
+        }else if OPERATOR_CHARS.contains(c) {
+            let index: usize = OPERATOR_CHARS.find(c).expect("c should be an operator character");
+            atoms.push(Atom::Operator(&OPERATOR_CHARS[index..index+1]));
+            1
+        }else {
+            let len: usize = rest.find(|c: char| c.is_whitespace() || OPERATOR_CHARS.contains(c)).unwrap_or(rest.len());
+            if c.is_ascii_digit() {
+                atoms.push(Atom::Number(parse_number(&rest[..len])?));
+            }else {
+                atoms.push(Atom::Name(rest[..len].to_string()));
+            };
+            len
+        };
+
+        rest = &rest[len..];
+    };
+}
+
+/// Parses a decimal, `0x` hexadecimal or `0` octal literal, as cmd's arithmetic does.<br><br>
+/// Hexadecimal and octal literals may use all 32 bits, while decimal ones must fit in a positive 32-bit integer.
+fn parse_number(text: &str) -> Option<i32> {
+    if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        u32::from_str_radix(hex, 16).ok().map(|value| value as i32)
+    }else if text.len() > 1 && text.starts_with('0') {
+        u32::from_str_radix(&text[1..], 8).ok().map(|value| value as i32)
+    }else if text.bytes().all(|byte| byte.is_ascii_digit()) {
+        text.parse().ok()
+    }else {
+        None
+    }
+}
+
+/// Reads the value of a variable by name, which counts as zero unless it holds a number, optionally negated.
+fn parse_value(value: &str) -> i32 {
+    let value: &str = value.trim();
+
+    match value.strip_prefix('-') {
+        Some(magnitude) => parse_number(magnitude).map_or(0, i32::wrapping_neg),
+        None => parse_number(value.strip_prefix('+').unwrap_or(value)).unwrap_or(0),
+    }
+}
+
+
+/// A recursive-descent evaluator over a lexed expression, with one method per level of cmd's operator precedence.
+struct Parser<'a> {
+    tokens: Vec<Atom>,
+    pos: usize,
+    depth: usize,
+    environment: &'a Environment,
+    assigned: HashMap<String, i32>,
+    evaluation: Evaluation,
+}
+
+impl Parser<'_> {
+
+    /// Consumes the next token if it is one of *operators*, returning which one it was.
+    fn operator(&mut self, operators: &[&'static str]) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Atom::Operator(operator)) if operators.contains(operator) => {
+                self.pos += 1;
+                Some(*operator)
+            },
+            _ => None,
+        }
+    }
+
+    /// Runs *step* one level deeper, giving up once the expression nests beyond *MAX_DEPTH*.
+    fn descend(&mut self, step: fn(&mut Self) -> Option<i32>) -> Option<i32> {
+        if self.depth == MAX_DEPTH {
+            return None;
+        };
+
+        self.depth += 1;
+        let value: Option<i32> = step(self);
+        self.depth -= 1;
+
+        value
+    }
+
+    /// Evaluates a comma-separated list of expressions, whose value is that of the last one.
+    fn list(&mut self) -> Option<i32> {
+        let mut value: i32 = self.assignment()?;
+        while self.operator(&[","]).is_some() {
+            value = self.assignment()?;
+        };
+
+        Some(value)
+    }
+
+    /// Evaluates an assignment, which groups to the right, or else a plain expression.
+    fn assignment(&mut self) -> Option<i32> {
+        let operator: Option<&'static str> = match (self.tokens.get(self.pos), self.tokens.get(self.pos + 1)) {
+            (Some(Atom::Name(_)), Some(Atom::Operator(operator))) if operator.ends_with('=') => Some(*operator),
+            _ => None,
+        };
+        let operator: &'static str = match operator {
+            Some(operator) => operator,
+            None => return self.binary(0),
+        };
+
+        let name: String = match &self.tokens[self.pos] {
+            Atom::Name(name) => name.clone(),
+            _ => unreachable!("an assignment should start with a name"),
+        };
+        self.pos += 2;
+
+        let rhs: i32 = self.descend(Self::assignment)?;
+        let value: i32 = match operator.strip_suffix('=').filter(|op| !op.is_empty()) {
+            Some(op) => apply(op, self.read(&name), rhs)?,
+            None => rhs,
+        };
+
+        self.assigned.insert(name.to_lowercase(), value);
+        self.evaluation.assignments.push((name, value));
+        Some(value)
+    }
+
+    /// Evaluates the binary operators from *level* of the precedence table downwards, all of which group to the left.
+    fn binary(&mut self, level: usize) -> Option<i32> {
+        const LEVELS: [&[&str]; 6] = [&["|"], &["^"], &["&"], &["<<", ">>"], &["+", "-"], &["*", "/", "%"]];
+
+        if level == LEVELS.len() {
+            return self.unary();
+        };
+
+        let mut value: i32 = self.binary(level + 1)?;
+        while let Some(op) = self.operator(LEVELS[level]) {
+            let rhs: i32 = self.binary(level + 1)?;
+            value = apply(op, value, rhs)?;
+        };
+
+        Some(value)
+    }
+
+    /// Evaluates the unary operators, which bind tighter than any binary one.
+    fn unary(&mut self) -> Option<i32> {
+        match self.operator(&["!", "~", "-", "+"]) {
+            Some("!") => Some((self.descend(Self::unary)? == 0) as i32),
+            Some("~") => Some(!self.descend(Self::unary)?),
+            Some("-") => Some(self.descend(Self::unary)?.wrapping_neg()),
+            Some(_) => self.descend(Self::unary),
+            None => self.primary(),
+        }
+    }
+
+    /// Evaluates a number, a variable or a parenthesised list.
+    fn primary(&mut self) -> Option<i32> {
+        if self.operator(&["("]).is_some() {
+            let value: i32 = self.descend(Self::list)?;
+            self.operator(&[")"])?;
+            return Some(value);
+        };
+
+        let value: i32 = match self.tokens.get(self.pos)? {
+            Atom::Number(number) => *number,
+            Atom::Name(name) => self.read(&name.clone()),
+            Atom::Operator(_) => return None,
+        };
+        self.pos += 1;
+
+        Some(value)
+    }
+
+    /// Reads a variable, preferring values assigned earlier in the same expression over the environment.
+    fn read(&mut self, name: &str) -> i32 {
+        if let Some(value) = self.assigned.get(&name.to_lowercase()) {
+            return *value;
+        };
+
+        if !self.evaluation.references.iter().any(|reference| reference.eq_ignore_ascii_case(name)) {
+            self.evaluation.references.push(name.to_string());
+        };
+        self.environment.get(name).map_or(0, parse_value)
+    }
+}
+
+/// Applies a binary operator with cmd's 32-bit semantics, returning *None* for a division by zero.
+fn apply(op: &str, lhs: i32, rhs: i32) -> Option<i32> {
+    let value: i32 = match op {
+        "|" => lhs | rhs,
+        "^" => lhs ^ rhs,
+        "&" => lhs & rhs,
+        "<<" => lhs.wrapping_shl(rhs as u32),
+        ">>" => lhs.wrapping_shr(rhs as u32),
+        "+" => lhs.wrapping_add(rhs),
+        "-" => lhs.wrapping_sub(rhs),
+        "*" => lhs.wrapping_mul(rhs),
+        "/" => lhs.checked_div(rhs).or_else(|| (rhs == -1).then(|| lhs.wrapping_neg()))?,
+        "%" => lhs.checked_rem(rhs).or_else(|| (rhs == -1).then_some(0))?,
+        _ => unreachable!("{op} should be a binary operator"),
+    };
+
+    Some(value)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(expression: &str) -> Option<i32> {
+        evaluate(expression, &Environment::new()).map(|evaluation| evaluation.value)
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(eval("1+2*3"), Some(7));
+        assert_eq!(eval("(1+2)*3"), Some(9));
+        assert_eq!(eval("1|6&3"), Some(3));
+        assert_eq!(eval("5^1<<1"), Some(7));
+        assert_eq!(eval("-7/2 , -7%2"), Some(-1));
+        assert_eq!(eval("!0+~0+- -1"), Some(1));
+        assert_eq!(eval("2147483647+1"), Some(i32::MIN));
+        assert_eq!(eval(" 1 << 33 "), Some(2));
+    }
+
+    #[test]
+    fn test_literals() {
+        assert_eq!(eval("0x70"), Some(112));
+        assert_eq!(eval("0160"), Some(112));
+        assert_eq!(eval("0xFFFFFFFF"), Some(-1));
+        assert_eq!(eval("08"), None);
+        assert_eq!(eval("2147483648"), None);
+        assert_eq!(eval("12ab"), None);
+    }
+
+    #[test]
+    fn test_assignments_and_references() {
+        let mut environment: Environment = Environment::new();
+        environment.set("a", "0x10");
+        environment.set("word", "text");
+
+        let evaluation: Evaluation = evaluate("x=a*2, y=x+=word+1, z=(q=3)<<1", &environment).unwrap();
+
+        assert_eq!(evaluation.value, 6);
+        let assignments: Vec<(&str, i32)> = evaluation.assignments.iter().map(|(name, value)| (name.as_str(), *value)).collect();
+        assert_eq!(assignments, [("x", 32), ("x", 33), ("y", 33), ("q", 3), ("z", 6)]);
+        assert_eq!(evaluation.references, ["a", "word"]);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(eval(""), None);
+        assert_eq!(eval("1/0"), None);
+        assert_eq!(eval("5%0"), None);
+        assert_eq!(eval("1+"), None);
+        assert_eq!(eval("(1"), None);
+        assert_eq!(eval("1 2"), None);
+        assert_eq!(eval("3=4"), None);
+        assert_eq!(eval("(1)<<=1"), None);
+    }
+
+    #[test]
+    fn test_nesting_limits() {
+        let nested = |depth: usize| format!("x={}1{}", "(".repeat(depth), ")".repeat(depth));
+
+        assert_eq!(eval(&nested(MAX_DEPTH - 1)), Some(1));
+        assert_eq!(eval(&nested(4000)), None);
+        assert_eq!(eval(&"-".repeat(4000)), None);
+        assert_eq!(eval(&format!("{}1", "a=".repeat(4000))), None);
+        assert_eq!(eval(&"1+".repeat(MAX_LINE_LENGTH)), None);
+    }
+}
@@ -19,20 +19,50 @@ OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 SOFTWARE. */
 
 use regex::Regex;
-use super::CharSet;
+use crate::{
+    Error,
+    Result,
+    batch::{
+        lexer::{
+            Blocks,
+            Token,
+            TokenKind,
+            tokenize,
+        },
+        emulator::{
+            Definition,
+            Emulated,
+            Emulator,
+            Environment,
+            Reparse,
+            Substitution,
+            Unresolved,
+            parse_set,
+        },
+        normalize::normalize,
+        report::DeobfuscationReport,
+    },
+};
 use std::{
-    fs::File,
+    fmt,
     io::Write,
-    process::exit,
-    collections::HashMap
+    collections::{
+        HashMap,
+        HashSet,
+    },
 };
 
 
+/// The number of layers a BatchDeobfuscator peels by default before giving up on reaching a fixpoint.
+pub const DEFAULT_MAX_DEPTH: usize = 8;
+
+
 /// ### An object that generates cleartext batch commands from obfuscated source commands.<br>
 /// ### Example Usage:<br>
 /// ```
 /// use oxidizer::batch::deobfuscator::BatchDeobfuscator;
 /// 
+/// # fn main() -> oxidizer::Result<()> {
 /// let poc = r"
 /// set HQTW=set
 /// %HQTW% HswBh= 
@@ -48,12 +78,12 @@ use std::{
 /// ";
 /// 
 /// let mut deobfuscator = BatchDeobfuscator::new();
-/// deobfuscator.initialize(poc.to_string());
+/// let deobfuscated = deobfuscator.deobfuscate_str(poc)?;
 /// 
-/// let deobfuscated_script = deobfuscator.write_deobfuscated_script(None);
-/// 
-/// // prints: "Deobfuscated code was written to: deobfuscated.bat".
-/// println!("Deobfuscated code was written to: {}", deobfuscated_script);
+/// assert_eq!(deobfuscated.code, "echo PoC");
+/// # Ok(())
+/// # }
+/// ```
 #[derive(Debug, Default)]
 pub struct BatchDeobfuscator {
     pub set_str: String,
@@ -61,56 +91,210 @@ pub struct BatchDeobfuscator {
     pub eq_str: String,
     pub alphabet: HashMap<String, String>,
     pub cleaned_code: String,
+    pub unresolved: Vec<Unresolved>,
+    pub substitutions: Vec<Substitution>,
+    pub layers: Vec<Layer>,
+    pub report: DeobfuscationReport,
+    environment: Environment,
+    max_depth: usize,
+    normalize: bool,
+    definitions: Vec<Definition>,
+    reparsed: Vec<Reparse>,
+    resolved: usize,
     initialized: bool,
 }
 
 
+/// The in-memory result of deobfuscating a script with a BatchDeobfuscator.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Deobfuscated {
+    pub code: String,
+    pub set_str: String,
+    pub space_str: String,
+    pub eq_str: String,
+    pub alphabet: HashMap<String, String>,
+    pub unresolved: Vec<Unresolved>,
+    pub substitutions: Vec<Substitution>,
+    pub layers: Vec<Layer>,
+    pub report: DeobfuscationReport,
+}
+
+
+/// The obfuscation technique which was peeled off to reveal a layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Technique {
+    /// An alphabet of single character variables, built from set/space/equals variables as written by BatchObfuscator.
+    Alphabet,
+    /// Variables defined and expanded in some other layout, recovered by emulating cmd's expansion.
+    Emulation,
+    /// Caret escapes and quotes which cmd would strip without changing anything, such as `p^o^w^e^r^s^h^e^l^l`.
+    Normalization,
+}
+
+impl fmt::Display for Technique {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Technique::Alphabet => write!(f, "obfuscated alphabet"),
+            Technique::Emulation => write!(f, "variable expansion"),
+            Technique::Normalization => write!(f, "caret and quote noise"),
+        }
+    }
+}
+
+
+/// One stage of a multi-layer deobfuscation: the code revealed by peeling a single technique off the previous layer.<br>
+/// Line numbers in *unresolved*, *substitutions* and *definitions* refer to the layer's input, which is the previous layer's code.<br>
+/// *resolved* counts the variable references which were replaced by their values, and *reparsed* lists the lines `call` expanded more than once.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Layer {
+    pub technique: Technique,
+    pub code: String,
+    pub unresolved: Vec<Unresolved>,
+    pub substitutions: Vec<Substitution>,
+    pub definitions: Vec<Definition>,
+    pub reparsed: Vec<Reparse>,
+    pub resolved: usize,
+}
+
+
 impl BatchDeobfuscator {
 
-    /// Creates a new, empty instance of a BatchDeobfuscator.
+    /// Creates a new, empty instance of a BatchDeobfuscator, which assumes the default environment of a stock Windows install.
     pub fn new() -> Self {
+        BatchDeobfuscator::with_environment(Environment::windows_defaults())
+    }
+
+    /// Creates a new, empty instance of a BatchDeobfuscator, which resolves environment variables from *environment* when emulating.
+    pub fn with_environment(environment: Environment) -> Self {
         BatchDeobfuscator {
             set_str: String::new(),
             space_str: String::new(),
             eq_str: String::new(),
             alphabet: HashMap::new(),
             cleaned_code: String::new(),
+            unresolved: Vec::new(),
+            substitutions: Vec::new(),
+            layers: Vec::new(),
+            report: DeobfuscationReport::default(),
+            environment,
+            max_depth: DEFAULT_MAX_DEPTH,
+            normalize: false,
+            definitions: Vec::new(),
+            reparsed: Vec::new(),
+            resolved: 0,
             initialized: false,
         }
     }
 
-    /// Initializes an empty BatchDeobfuscator, reverse_engineers an obfuscated alphabet, and attempts to deobfuscate the provided source code.
-    pub fn initialize(&mut self, src: String) {
+    /// Limits how many layers of obfuscation are peeled, where *1* only removes the outermost one.<br>
+    /// Defaults to *DEFAULT_MAX_DEPTH*.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth.max(1);
+        self
+    }
+
+    /// Also strips caret-escape and quote noise, as a layer of its own, once no other technique applies. Off by default.<br>
+    /// The result runs just like the input, but no longer matches it byte for byte, so round-trip checks should leave this off.
+    pub fn normalize(mut self, enabled: bool) -> Self {
+        self.normalize = enabled;
+        self
+    }
+
+    /// Initializes an empty BatchDeobfuscator, reverse_engineers an obfuscated alphabet, and attempts to deobfuscate the provided source code.<br><br>
+    /// Sources without the expected set/space/equals variables are handed to an *Emulator* instead, which replays cmd's variable expansion.<br>
+    /// Returns **Error::IncompatibleInput** if neither approach recovers anything.<br><br>
+    /// The result is deobfuscated again until it stops changing or *max_depth* layers have been peeled, and every layer is kept in *layers*.<br>
+    /// Below the outermost layer, emulation is only attempted when variables build the commands themselves, so that a script's own variables are left alone.<br>
+    /// The alphabet and set/space/equals variables describe the outermost layer, while *unresolved* describes the innermost one.
+    pub fn initialize(&mut self, src: String) -> Result<()> {
+
+        // Start from a clean slate, so that nothing leaks between inputs.
+        let environment: Environment = std::mem::take(&mut self.environment);
+        let max_depth: usize = self.max_depth;
+        let normalize: bool = self.normalize;
+        *self = BatchDeobfuscator::with_environment(environment).max_depth(max_depth).normalize(normalize);
+
+        let mut layers: Vec<Layer> = Vec::new();
+        let mut outermost: Option<BatchDeobfuscator> = None;
+        let mut code: String = src;
+        while layers.len() < max_depth {
+            let mut peeler: BatchDeobfuscator = BatchDeobfuscator::with_environment(self.environment.clone()).normalize(normalize);
+            let technique: Technique = match peeler.peel(code.clone(), !layers.is_empty()) {
+                Ok(technique) => technique,
+                Err(err) if layers.is_empty() => return Err(err),
+                Err(_) => break,
+            };
+
+            if !layers.is_empty() && peeler.cleaned_code == code {
+                break;
+            };
+
+            code = peeler.cleaned_code.clone();
+            layers.push(Layer {
+                technique,
+                code: peeler.cleaned_code.clone(),
+                unresolved: std::mem::take(&mut peeler.unresolved),
+                substitutions: std::mem::take(&mut peeler.substitutions),
+                definitions: std::mem::take(&mut peeler.definitions),
+                reparsed: std::mem::take(&mut peeler.reparsed),
+                resolved: peeler.resolved,
+            });
+            outermost.get_or_insert(peeler);
+        };
+
+        if let Some(outermost) = outermost {
+            self.set_str = outermost.set_str;
+            self.space_str = outermost.space_str;
+            self.eq_str = outermost.eq_str;
+            self.alphabet = outermost.alphabet;
+        };
+        self.cleaned_code = code;
+        self.unresolved = layers.last().map(|layer| layer.unresolved.clone()).unwrap_or_default();
+        self.substitutions = layers.iter().flat_map(|layer| layer.substitutions.iter().cloned()).collect();
+        self.report = DeobfuscationReport::from_layers(&layers);
+        self.layers = layers;
+
+        self.initialized = true;
+        Ok(())
+    }
+
+    /// Peels a single layer of obfuscation off the source, and reports which technique it was.<br>
+    /// A *nested* layer is only emulated when its commands are built from variables.
+    fn peel(&mut self, src: String, nested: bool) -> Result<Technique> {
 
         // Pattern matching to identify set, space, and equals variables.
         let re_set = Regex::new(
-            r"set [a-zA-Z0-9!#\$\*\(\)\[\]\{\},-\.\?@_~]+=set"
+            r"set ([a-zA-Z0-9!#\$\*\(\)\[\]\{\},-\.\?@_~]+)=set"
         ).expect("Regex pattern invalid!");
         let re_space = Regex::new(
-            r"%[a-zA-Z0-9!#\$\*\(\)\[\]\{\},-\.\?@_~]+% [a-zA-Z0-9!#\$\*\(\)\[\]\{\},-\.\?@_~]+= "
+            r"%([a-zA-Z0-9!#\$\*\(\)\[\]\{\},-\.\?@_~]+)% ([a-zA-Z0-9!#\$\*\(\)\[\]\{\},-\.\?@_~]+)= "
         ).expect("Regex pattern invalid!");
         let re_equal = Regex::new(
-            r"%[a-zA-Z0-9!#\$\*\(\)\[\]\{\},-\.\?@_~]+%[a-zA-Z0-9!#\$\*\(\)\[\]\{\},-\.\?@_~]+=="
+            r"%([a-zA-Z0-9!#\$\*\(\)\[\]\{\},-\.\?@_~]+)%%([a-zA-Z0-9!#\$\*\(\)\[\]\{\},-\.\?@_~]+)%([a-zA-Z0-9!#\$\*\(\)\[\]\{\},-\.\?@_~]+)=="
         ).expect("Regex pattern invalid!");
-        let set_match: Vec<&str> = re_set.find_iter(&src).map(|mat| mat.as_str()).collect();
-        let space_match: Vec<&str> = re_space.find_iter(&src).map(|mat| mat.as_str()).collect();
-        let equal_match: Vec<&str> = re_equal.find_iter(&src).map(|mat| mat.as_str()).collect();
-
-        if set_match.is_empty() ||
-            space_match.is_empty() ||
-            equal_match.is_empty()
-        {
-            eprintln!("\nInput script does not appear to be compatible with deobfuscation!");
-            exit(1);
+
+        let set_str: &str = match re_set.captures(&src) {
+            Some(caps) => caps.get(1).map_or("", |mat| mat.as_str()),
+            None => return self.fallback(&src, nested),
         };
-        
+
+        // The space and equals definitions must be built on top of the set variable identified above.
+        let space_str: &str = match re_space.captures_iter(&src).find(|caps| &caps[1] == set_str) {
+            Some(caps) => caps.get(2).map_or("", |mat| mat.as_str()),
+            None => return self.fallback(&src, nested),
+        };
+        let eq_str: &str = match re_equal.captures_iter(&src).find(|caps| &caps[1] == set_str && &caps[2] == space_str) {
+            Some(caps) => caps.get(3).map_or("", |mat| mat.as_str()),
+            None => return self.fallback(&src, nested),
+        };
+
         // Extract the proper variable strings based on the structure of the obfuscation.
-        let set_str: &str = set_match[0].split(' ').collect::<Vec<&str>>()[1];
-        let space_str: &str = space_match[0].split(' ').collect::<Vec<&str>>()[1];
-        let eq_str: &str = equal_match[0].split('%').collect::<Vec<&str>>()[2];
-        self.set_str = set_str[0..set_str.len()-4].to_string();
-        self.space_str = space_str[0..space_str.len()-2].to_string();
-        self.eq_str = eq_str[0..eq_str.len()-2].to_string();
+        self.set_str = set_str.to_string();
+        self.space_str = space_str.to_string();
+        self.eq_str = eq_str.to_string();
 
         // Reverse engineer the obfuscated alphabet and build a cleartext charset.
         self.reverse_alphabet(&src);
@@ -118,144 +302,398 @@ impl BatchDeobfuscator {
         // Deobfuscate the source code using the reverse-engineered obfuscation alphabet.
         self.deobfuscate(src);
 
-        self.initialized = true;
+        Ok(Technique::Alphabet)
     }
 
-    /// Writes the deobfuscated source of a pre-initialized BatchDeobfuscator to a file, and returns a string containing the name of that file.<br><br>
-    /// Output filename defaults to *deobfuscated.bat* when **None** is passed into the parameter.<br>
-    /// 
-    /// # Panics
-    /// **This method panics if file creation/writing fails.**
-    pub fn write_deobfuscated_script(&self, file_name: Option<String>) -> String {
-
-        if !self.initialized { 
-            eprintln!("\nDeobfuscator must first be initialized!");
-            std::process::exit(1);
-        };
-
-        let handle_name: String = file_name.unwrap_or_else(|| String::from("deobfuscated.bat"));
-        let handle_clone: String = handle_name.clone();
-
-        let mut file = File::create(handle_clone.as_str()).expect("Failed to create file!");
-        file.write_all(self.cleaned_code.as_bytes()).expect("Failed writing to file!");
-
-        handle_name
+    /// Peels a layer from a script in a layout other than our own, by emulation or else by normalization if it is enabled.
+    fn fallback(&mut self, src: &str, nested: bool) -> Result<Technique> {
+        match self.emulate(src, nested) {
+            Err(Error::IncompatibleInput) if self.normalize => self.strip_noise(src),
+            result => result,
+        }
     }
 
-    /// Reverse-engineers an obfuscated alphabet using known patterns in the obfuscated source code.
-    fn reverse_alphabet(&mut self, src: &str) {
+    /// Strips caret-escape and quote noise from a script, failing if there was none.
+    fn strip_noise(&mut self, src: &str) -> Result<Technique> {
+        let normalized: String = normalize(src);
 
-        let re = Regex::new(
-            r"[a-zA-Z0-9!#\$\*\(\)\[\]\{\},-\.\?@_~]+%[a-zA-Z0-9!#\$\*\(\)\[\]\{\},-\.\?@_~]+%.{1}\n"
-        ).expect("Regex pattern invalid!");
+        if normalized == src {
+            return Err(Error::IncompatibleInput);
+        };
 
-        let matches: Vec<&str> = re.find_iter(src).map(|mat| mat.as_str()).collect();
+        self.cleaned_code = normalized;
+        Ok(Technique::Normalization)
+    }
 
-        for mtch in matches {
-            let chr: String = format!("{}", mtch.chars().nth(mtch.len()-2).unwrap());
+    /// Deobfuscates a script in a layout other than our own by emulating cmd's variable expansion.<br><br>
+    /// The alphabet is filled with every variable left defined at the end of the script.
+    fn emulate(&mut self, src: &str, nested: bool) -> Result<Technique> {
+        if nested && !builds_commands(src) {
+            return Err(Error::IncompatibleInput);
+        };
 
-            let name: String = String::from(mtch.split('%').collect::<Vec<&str>>()[0]);
+        let emulated: Emulated = Emulator::with_environment(self.environment.clone()).run(src);
 
-            self.alphabet.insert(name, chr);
+        if emulated.expansions == 0 {
+            return Err(Error::IncompatibleInput);
         };
 
-        let re2 = Regex::new(
-            r"[a-zA-Z0-9!#\$\*\(\)\[\]\{\},-\.\?@_~]+%[a-zA-Z0-9!#\$\*\(\)\[\]\{\},-\.\?@_~]+%.{2}\n"
-        ).expect("Regex pattern invalid!");
-
-        let matches: Vec<&str> = re2.find_iter(src).map(|mat| mat.as_str()).collect();
+        self.alphabet = emulated.environment.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        self.cleaned_code = emulated.code;
+        self.unresolved = emulated.unresolved;
+        self.substitutions = emulated.substitutions;
+        self.definitions = emulated.definitions;
+        self.reparsed = emulated.reparsed;
+        self.resolved = emulated.expansions;
+
+        Ok(Technique::Emulation)
+    }
 
-        for mtch in matches {
-            let blob: String = format!(
-                "{}{}",
-                mtch.chars().nth(mtch.len()-3).unwrap(),
-                mtch.chars().nth(mtch.len()-2).unwrap()
-            );
+    /// Deobfuscates the provided source code entirely in memory.<br><br>
+    /// Returns the cleartext script along with the alphabet that was reverse-engineered from it.
+    pub fn deobfuscate_str(&mut self, src: &str) -> Result<Deobfuscated> {
+        self.initialize(src.to_string())?;
+
+        Ok(Deobfuscated {
+            code: self.cleaned_code.clone(),
+            set_str: self.set_str.clone(),
+            space_str: self.space_str.clone(),
+            eq_str: self.eq_str.clone(),
+            alphabet: self.alphabet.clone(),
+            unresolved: self.unresolved.clone(),
+            substitutions: self.substitutions.clone(),
+            layers: self.layers.clone(),
+            report: self.report.clone(),
+        })
+    }
 
-            let name: String = String::from(mtch.split('%').collect::<Vec<&str>>()[0]);
+    /// Writes the deobfuscated source of a pre-initialized BatchDeobfuscator to any writer, such as a file, socket, buffer or stdout.<br><br>
+    /// Returns **Error::Uninitialized** if called before *initialize*, or **Error::Io** if writing fails.
+    pub fn write_deobfuscated_script<W: Write>(&self, mut writer: W) -> Result<()> {
 
-            self.alphabet.insert(name, blob);
+        if !self.initialized { 
+            return Err(Error::Uninitialized);
         };
 
-        let re3 = Regex::new(
-            r"%[a-zA-Z0-9!#\$\*\(\)\[\]\{\},-\.\?@_~]+%%[a-zA-Z0-9!#\$\*\(\)\[\]\{\},-\.\?@_~]+%[a-zA-Z0-9!#\$\*\(\)\[\]\{\},-\.\?@_~]+%[a-zA-Z0-9!#\$\*\(\)\[\]\{\},-\.\?@_~]+%.{3,}\n"
-        ).expect("Regex pattern invalid!");
+        writer.write_all(self.cleaned_code.as_bytes())?;
+        writer.flush()?;
 
-        let matches: Vec<&str> = re3.find_iter(src).map(|mat| mat.as_str()).collect();
+        Ok(())
+    }
 
-        for mtch in matches {
-            match mtch.find(&self.eq_str) {
-                Some(index) => {
-                    let name_haystack: Vec<&str> = mtch.split('%').collect();
-                    let name: String = String::from(name_haystack[name_haystack.len()-3]);
-                    let blob: &str = &mtch[(index+self.eq_str.len()+1)..];
-                    println!("name {:#?}", mtch.split('%').collect::<Vec<&str>>());
-                    self.alphabet.insert(name, blob.to_string());
-                },
-                None => {
-                    continue;
-                },
+    /// Reverse-engineers an obfuscated alphabet from the variable definition statements in the obfuscated source code.<br><br>
+    /// Definitions take the form *%set%%space%NAME%eq%VALUE*, where VALUE may be a single character or a longer blob.
+    fn reverse_alphabet(&mut self, src: &str) {
+
+        for (line_num, line) in src.split('\n').enumerate() {
+            if let Some((name, value)) = self.parse_definition(line) {
+                self.alphabet.insert(name.to_string(), value.to_string());
+                self.definitions.push(Definition { line: line_num + 1, name: name.to_string(), value: value.to_string(), used: false });
             };
         };
     }
 
-    /// Deobfuscates a sample of obfuscated batch commands using a reverse-engineered obfuscation alphabet.
+    /// Deobfuscates a sample of obfuscated batch commands using a reverse-engineered obfuscation alphabet.<br><br>
+    /// Like cmd, each line only sees the definitions made before the block or chained line it belongs to,
+    /// so a definition inside a block takes effect once the block has ended.
     fn deobfuscate(&mut self, src: String) {
 
-        let src: Vec<&str> = src.split('\n').collect();
+        let watermarks: [&str; 2] = [
+            ":: VGhpcyBmaWxlIHdhcyBvYmZ1c2NhdGVkIHZpYSBodHRwczovL2dpdGh1Yi5jb20vMHhUYXMvMHhpZGl6M3I=",
+            ":: VGhpcyBmaWxlIGNhbiBiZSBwcm9ncmFtYXRpY2FsbHkgZGVvYmZ1c2NhdGVkIHZpYSBodHRwczovL2dpdGh1Yi5jb20vMHhUYXMvMHhpZGl6M3I=",
+        ];
+        let set_line: String = format!("set {}=set", self.set_str);
+        let space_line: String = format!("%{}% {}= ", self.set_str, self.space_str);
+        let eq_line: String = format!("%{}%%{}%{}==", self.set_str, self.space_str, self.eq_str);
 
         // Iterate over the remaining obfuscated text and map the obfuscated strings to cleartext characters.
-        let mut cleaned_chars: Vec<String> = Vec::new();
-        for line in src {
+        let mut cleaned_lines: Vec<String> = Vec::new();
+        let mut resolved: Vec<String> = Vec::new();
+        let mut unresolved: Vec<Unresolved> = Vec::new();
+        let mut seen_prelude: bool = false;
+        let mut visible: HashMap<String, String> = HashMap::new();
+        let mut pending: Vec<(String, String)> = Vec::new();
+        let mut blocks: Blocks = Blocks::new();
+        let mut open: bool = false;
+        // Editors and git may convert the script to CRLF, which adds a carriage return to every line that cmd strips again.
+        let converted: bool = src.split('\n').any(|line| line.strip_suffix('\r') == Some(set_line.as_str()));
+        for (line_num, line) in src.split('\n').enumerate() {
+            let line: &str = if converted { line.strip_suffix('\r').unwrap_or(line) } else { line };
+
+            if line == set_line || line == space_line || line == eq_line {
+                seen_prelude = true;
+                continue;
+            };
+            if let Some((name, value)) = self.parse_definition(line) {
+                seen_prelude = true;
+                pending.push((name.to_string(), value.to_string()));
+                if !open {
+                    visible.extend(pending.drain(..));
+                };
+                continue;
+            };
 
-            if line == "\n" || line == "\r\n" || line.is_empty() { continue; };
+            if watermarks.contains(&line.trim_end_matches('\r')) {
+                continue;
+            };
 
-            if line.contains(&self.set_str) || line.contains(&self.space_str) || line.contains(&self.eq_str) {
+            // The obfuscator switches code pages ahead of its definitions when encoding non-ASCII characters.
+            if !seen_prelude && is_chcp_line(line) {
                 continue;
             };
 
-            if line.contains(":: VGhpcyBmaWxlIHdhcyBvYmZ1c2NhdGVkIHZpYSBodHRwczovL2dpdGh1Yi5jb20vMHhUYXMvMHhpZGl6M3I=") 
-            || line.contains(":: VGhpcyBmaWxlIGNhbiBiZSBwcm9ncmFtYXRpY2FsbHkgZGVvYmZ1c2NhdGVkIHZpYSBodHRwczovL2dpdGh1Yi5jb20vMHhUYXMvMHhpZGl6M3I="){
+            // Blank lines ahead of the obfuscation prelude are padding, but blank lines after it belong to the script.
+            if !seen_prelude && line.trim().is_empty() {
                 continue;
             };
 
-            let code: Vec<&str> = line.split('%').collect();
-
-            for blob in code {
-                if let Some(chr) = self.alphabet.get(&blob.to_string()) {
-                    cleaned_chars.push(chr.to_string());
-                }else {
-                    for c in self.alphabet.keys() {
-                        if blob.contains(c.as_str()) { continue };
-                    };
-
-                    if !blob.contains(' ') && !blob.is_empty() && blob != "\r" {
-                        let mut skip: bool = false;
-                        for character in blob.chars() {
-                            if CharSet::BadChars.values().contains(&character) {
-                                skip = true;
-                            };
-                        };
-                        if !skip { cleaned_chars.push(String::from("%")); };
-                        for character in blob.chars() {
-                            cleaned_chars.push(character.to_string());
-                        };
-                        if !skip { cleaned_chars.push(String::from("%")); };
-                    }else {
-                        for character in blob.chars() {
-                            cleaned_chars.push(character.to_string());
-                        };
-                    };
-                };
+            let decoded: String = decode_line(line, line_num + 1, &visible, &mut resolved, &mut unresolved);
+
+            // Blocks are found in the decoded text, just as cmd finds them after percent expansion.
+            let tokens: Vec<Token> = tokenize(&decoded, false);
+            blocks.commands(&tokens);
+            open = blocks.is_open(&tokens);
+            if !open {
+                visible.extend(pending.drain(..));
             };
 
-            cleaned_chars.push(String::from("\n"));
+            cleaned_lines.push(decoded);
+        };
+
+        // Count the references which were decoded, and note the alphabet entries they used.
+        self.resolved = resolved.len();
+        let used: HashSet<&str> = resolved.iter().map(String::as_str).collect();
+        for definition in self.definitions.iter_mut() {
+            definition.used = used.contains(definition.name.as_str());
         };
 
-        // Clean up the last remaining artifacts of the obfuscation.
-        cleaned_chars = cleaned_chars.join("").split('\n').map(|chr| chr.to_string()).collect();
+        // References to variables the script assigns itself, or which the environment provides, are resolved when it runs.
+        let assigned: HashSet<String> = cleaned_lines.iter()
+            .filter_map(|line| parse_set(line))
+            .map(|(name, _)| name.to_lowercase())
+            .collect();
+        self.unresolved = unresolved.into_iter()
+            .filter(|reference| !assigned.contains(&reference.name.to_lowercase()) && self.environment.get(&reference.name).is_none())
+            .collect();
+
+        // The obfuscator terminates every source line with a newline, which leaves one empty line behind.
+        if cleaned_lines.last().map(|line| line.is_empty()).unwrap_or(false) {
+            cleaned_lines.pop();
+        };
 
         // Reassemble the cleartext code and finalize the initialization.
-        self.cleaned_code = cleaned_chars.join("\n").trim_end().to_string();
+        self.cleaned_code = cleaned_lines.join("\n");
+    }
+
+    /// Splits an obfuscated variable definition statement into its name and value, if the line is one.<br>
+    /// A trailing carriage return is not part of the value, since cmd strips it before the line runs.
+    fn parse_definition<'a>(&self, line: &'a str) -> Option<(&'a str, &'a str)> {
+        let line: &str = line.trim_end_matches('\r');
+        let prefix: String = format!("%{}%%{}%", self.set_str, self.space_str);
+        let separator: String = format!("%{}%", self.eq_str);
+
+        let rest: &str = line.strip_prefix(prefix.as_str())?;
+        let name_end: usize = rest.find('%')?;
+        let value: &str = rest[name_end..].strip_prefix(separator.as_str())?;
+
+        if name_end == 0 { return None };
+
+        Some((&rest[..name_end], value))
+    }
+}
+
+
+/// Replaces every *%NAME%* reference to a variable of *alphabet* in a line, leaving everything else untouched.<br>
+/// The names of decoded references are added to *resolved*, and any other reference is added to *unresolved*.
+fn decode_line(line: &str, line_num: usize, alphabet: &HashMap<String, String>, resolved: &mut Vec<String>, unresolved: &mut Vec<Unresolved>) -> String {
+    tokenize(line, false).iter()
+        .map(|token| {
+            if token.kind != TokenKind::Variable {
+                return token.text;
+            };
+
+            let name: &str = &token.text[1..token.text.len()-1];
+            match alphabet.get(name) {
+                Some(value) => {
+                    resolved.push(name.to_string());
+                    value.as_str()
+                },
+                None => {
+                    unresolved.push(Unresolved {
+                        line: line_num,
+                        span: token.span.clone(),
+                        name: token.variable_name().unwrap_or_default().to_string(),
+                    });
+                    token.text
+                },
+            }
+        })
+        .collect()
+}
+
+/// Returns *true* for a `chcp N>nul` line, as written by the obfuscator ahead of its definitions.
+fn is_chcp_line(line: &str) -> bool {
+    line.trim_end_matches('\r')
+        .strip_prefix("chcp ")
+        .and_then(|rest| rest.strip_suffix(">nul"))
+        .is_some_and(|code_page| code_page.parse::<u32>().is_ok())
+}
+
+/// Returns *true* if any line starts its command with a variable reference, which is how obfuscators hide the commands they run.
+fn builds_commands(src: &str) -> bool {
+    src.split('\n').any(|line| {
+        tokenize(line, true).iter()
+            .find(|token| token.kind != TokenKind::Whitespace && token.text != "@")
+            .is_some_and(|token| matches!(token.kind, TokenKind::Variable | TokenKind::DelayedVariable))
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_emulation_fallback() {
+        let src: &str = "set \"a=ec\"\nset b=ho\n%A%%b% %msg%";
+
+        let deobfuscated: Deobfuscated = BatchDeobfuscator::new().deobfuscate_str(src).unwrap();
+
+        assert_eq!(deobfuscated.code, "echo %msg%");
+        assert_eq!(deobfuscated.alphabet.get("a").map(String::as_str), Some("ec"));
+        assert_eq!(deobfuscated.unresolved.len(), 1);
+        assert!(matches!(BatchDeobfuscator::new().deobfuscate_str("echo plain"), Err(Error::IncompatibleInput)));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_environment_survives_reinitialization() {
+        let mut environment: Environment = Environment::new();
+        environment.set("alpha", "abcdefghijklmnopqrstuvwxyz");
+
+        let mut deobfuscator: BatchDeobfuscator = BatchDeobfuscator::with_environment(environment);
+        deobfuscator.deobfuscate_str("%alpha:~4,1%cho").unwrap();
+
+        let deobfuscated: Deobfuscated = deobfuscator.deobfuscate_str("%ALPHA:~-1%%ComSpec:~-1%").unwrap();
+        assert_eq!(deobfuscated.code, "z%ComSpec:~-1%");
+        assert_eq!(deobfuscated.unresolved.len(), 1);
+    }
+
+    #[test]
+    fn test_crlf_converted_output() {
+        use crate::batch::obfuscator::ObfuscatorConfig;
+
+        let src: &str = "@echo off\r\necho 100%% done\r\nrem mixed\nexit /b\r\n";
+        let obfuscated: String = ObfuscatorConfig::new().seed(17).build().unwrap().obfuscate_str(src).unwrap().code;
+
+        // Output that was never converted still round-trips byte for byte.
+        assert_eq!(BatchDeobfuscator::new().deobfuscate_str(&obfuscated).unwrap().code, src);
+
+        // Converting the obfuscated script to CRLF does not change the recovered script either.
+        let converted: String = obfuscated.replace('\n', "\r\n");
+        let deobfuscated: Deobfuscated = BatchDeobfuscator::new().deobfuscate_str(&converted).unwrap();
+        assert_eq!(deobfuscated.code, src);
+        assert!(deobfuscated.alphabet.values().all(|value| !value.ends_with('\r')));
+    }
+
+    #[test]
+    fn test_layers_are_peeled_until_fixpoint() {
+        use crate::batch::obfuscator::ObfuscatorConfig;
+
+        let src: &str = "set a=ec\nset b=ho\n%a%%b% peeled\n";
+        let inner: String = ObfuscatorConfig::new().seed(1).build().unwrap().obfuscate_str(src).unwrap().code;
+        let outer: String = ObfuscatorConfig::new().seed(2).build().unwrap().obfuscate_str(&inner).unwrap().code;
+
+        let deobfuscated: Deobfuscated = BatchDeobfuscator::new().deobfuscate_str(&outer).unwrap();
+        let techniques: Vec<Technique> = deobfuscated.layers.iter().map(|layer| layer.technique).collect();
+        assert_eq!(techniques, [Technique::Alphabet, Technique::Alphabet, Technique::Emulation]);
+        assert_eq!(deobfuscated.layers[0].code, inner);
+        assert_eq!(deobfuscated.layers[1].code, src);
+        assert_eq!(deobfuscated.code, "echo peeled\n");
+
+        let shallow: Deobfuscated = BatchDeobfuscator::new().max_depth(1).deobfuscate_str(&outer).unwrap();
+        assert_eq!(shallow.layers.len(), 1);
+        assert_eq!(shallow.code, inner);
+    }
+
+    #[test]
+    fn test_script_variables_are_not_peeled() {
+        use crate::batch::obfuscator::ObfuscatorConfig;
+
+        let src: &str = "set name=world\necho hello %name%";
+        let obfuscated: String = ObfuscatorConfig::new().seed(3).build().unwrap().obfuscate_str(src).unwrap().code;
+
+        let deobfuscated: Deobfuscated = BatchDeobfuscator::new().deobfuscate_str(&obfuscated).unwrap();
+        assert_eq!(deobfuscated.layers.len(), 1);
+        assert_eq!(deobfuscated.code, src);
+    }
+
+    #[test]
+    fn test_definitions_in_blocks_apply_after_the_block() {
+        use crate::batch::obfuscator::{Obfuscated, ObfuscatorConfig};
+
+        let obfuscated: Obfuscated = ObfuscatorConfig::new().seed(5).watermark(false).build().unwrap().obfuscate_str("echo go").unwrap();
+        let definition: String = format!("%{}%%{}%late%{}%X", obfuscated.set_str, obfuscated.space_str, obfuscated.eq_str);
+        let src: String = format!("{}if 1==1 (\n{}\necho %late%\n) & echo %late%\necho %late%", obfuscated.code, definition);
+
+        let deobfuscated: Deobfuscated = BatchDeobfuscator::new().deobfuscate_str(&src).unwrap();
+        assert_eq!(deobfuscated.code, "echo go\nif 1==1 (\necho %late%\n) & echo %late%\necho X");
+        let names: Vec<&str> = deobfuscated.report.unresolved.iter().map(|reference| reference.name.as_str()).collect();
+        assert_eq!(names, ["late", "late"]);
+    }
+
+    #[test]
+    fn test_report_distinguishes_partial_recovery() {
+        use crate::batch::obfuscator::ObfuscatorConfig;
+
+        let src: &str = "set name=world\necho %name% %missing% %windir%";
+        let obfuscated: String = ObfuscatorConfig::new().seed(4).build().unwrap().obfuscate_str(src).unwrap().code;
+
+        // Only references that nothing will ever define count against the recovery.
+        let complete: Deobfuscated = BatchDeobfuscator::new().deobfuscate_str(&obfuscated).unwrap();
+        let names: Vec<&str> = complete.report.unresolved.iter().map(|reference| reference.name.as_str()).collect();
+        assert_eq!(names, ["missing"]);
+        assert!(complete.report.resolved > 0);
+        assert!(complete.report.unused.iter().any(|unused| complete.alphabet.get(&unused.name).map(String::as_str) == Some("Z")));
+        assert!(complete.report.duplicates.is_empty());
+
+        // Dropping a definition from the alphabet leaves its references behind, and lowers the coverage.
+        let eq_prefix: String = format!("%{}%%{}%", complete.set_str, complete.space_str);
+        let damaged: String = obfuscated.lines()
+            .filter(|line| !(line.starts_with(&eq_prefix) && line.ends_with(&format!("%{}%c", complete.eq_str))))
+            .collect::<Vec<&str>>()
+            .join("\n");
+        let partial: Deobfuscated = BatchDeobfuscator::new().deobfuscate_str(&damaged).unwrap();
+        assert!(partial.report.unresolved.len() > 1);
+        assert!(partial.report.coverage() < complete.report.coverage());
+    }
+
+    #[test]
+    fn test_noise_is_normalized_on_request() {
+        let src: &str = "set a=ec\nset b=ho\n%a%%b% p^o^w^e^r^s^h^e^l^l ^& c\"\"alc";
+
+        let plain: Deobfuscated = BatchDeobfuscator::new().deobfuscate_str(src).unwrap();
+        assert_eq!(plain.code, "echo p^o^w^e^r^s^h^e^l^l ^& c\"\"alc");
+
+        let normalized: Deobfuscated = BatchDeobfuscator::new().normalize(true).deobfuscate_str(src).unwrap();
+        let techniques: Vec<Technique> = normalized.layers.iter().map(|layer| layer.technique).collect();
+        assert_eq!(techniques, [Technique::Emulation, Technique::Normalization]);
+        assert_eq!(normalized.code, "echo powershell ^& c\"\"alc");
+
+        assert!(matches!(BatchDeobfuscator::new().deobfuscate_str("c^a^l^c"), Err(Error::IncompatibleInput)));
+        assert_eq!(BatchDeobfuscator::new().normalize(true).deobfuscate_str("c^a^l^c").unwrap().code, "calc");
+    }
+
+    #[test]
+    fn test_call_reparse_is_reported() {
+        let src: &str = "set a=ec\nset b=ho\nset n=ab\ncall set c=%%%n:~0,1%%%%%%n:~1,1%%%\n%c% done";
+
+        let deobfuscated: Deobfuscated = BatchDeobfuscator::new().deobfuscate_str(src).unwrap();
+
+        assert_eq!(deobfuscated.code, "echo done");
+        assert_eq!(deobfuscated.report.reparsed.len(), 1);
+        assert_eq!((deobfuscated.report.reparsed[0].line, deobfuscated.report.reparsed[0].rounds), (4, 2));
+        assert!(deobfuscated.report.to_string().contains("layer 1, line 4: 2 rounds"));
+    }
+}
@@ -0,0 +1,97 @@
+/* Copyright (c) 2022 Zach Griffin (0xTas)
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE. */
+
+
+use std::{
+    fmt,
+    ops::Range,
+    sync::Arc,
+};
+
+
+/// The kind of construct that could not be obfuscated, and was therefore left in cleartext.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DiagnosticKind {
+    /// A reference to a variable which is defined elsewhere in the input script.
+    UserVariable,
+    /// A reference to a variable which is never defined in the input script, presumably from the environment.
+    EnvironmentVariable,
+    /// A function label, such as `:EXAMPLE`.
+    Label,
+    /// A line assigning a variable with `set`.
+    SetLine,
+    /// A reference to a variable which a `set` earlier in the same block or `&`-chained line of the input assigns.
+    /// cmd expands the whole unit before running it, so the reference still sees the old value.<br>
+    /// Only the input's own `set` commands are checked, since the obfuscator writes every definition of its own to the prelude, ahead of any block.
+    SameBlockReference,
+    /// A line whose obfuscated form would exceed cmd.exe's line length limit, even with the shortest variable names.
+    LineTooLong { projected: usize },
+}
+
+/// A note about part of the input script which could not be effectively obfuscated.<br><br>
+/// *line* is 1-based, and *span* is the byte range of the offending construct within that line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Diagnostic {
+    pub line: usize,
+    pub span: Range<usize>,
+    pub kind: DiagnosticKind,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind: String = match self.kind {
+            DiagnosticKind::UserVariable => String::from("user-defined variable"),
+            DiagnosticKind::EnvironmentVariable => String::from("environment variable"),
+            DiagnosticKind::Label => String::from("function label"),
+            DiagnosticKind::SetLine => String::from("set statement"),
+            DiagnosticKind::SameBlockReference => String::from("variable expanded before its assignment in the same block runs"),
+            DiagnosticKind::LineTooLong { projected } => format!("obfuscated line would be {} characters long", projected),
+        };
+
+        write!(f, "line {}, columns {}..{}: {}", self.line, self.span.start, self.span.end, kind)
+    }
+}
+
+/// A callback which decides whether to pass diagnosed constructs through, and may capture whatever state it needs to do so.
+pub type DiagnosticCallback = Arc<dyn Fn(&[Diagnostic]) -> bool + Send + Sync>;
+
+/// Decides what the obfuscator does when the input contains constructs it cannot obfuscate.
+#[derive(Clone, Default)]
+pub enum DiagnosticPolicy {
+    /// Stop obfuscating and return **Error::Aborted**.
+    Abort,
+    /// Print the offending lines as-is in order to preserve functionality.
+    #[default]
+    Passthrough,
+    /// Hand the collected diagnostics to a callback, which returns *true* to pass the lines through or *false* to abort.
+    Ask(DiagnosticCallback),
+}
+
+impl fmt::Debug for DiagnosticPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiagnosticPolicy::Abort => write!(f, "Abort"),
+            DiagnosticPolicy::Passthrough => write!(f, "Passthrough"),
+            DiagnosticPolicy::Ask(_) => write!(f, "Ask(..)"),
+        }
+    }
+}
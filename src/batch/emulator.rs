@@ -0,0 +1,1209 @@
+/* Copyright (c) 2022 Zach Griffin (0xTas)
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE. */
+
+
+use std::{
+    ops::Range,
+    collections::{
+        HashMap,
+        HashSet,
+    },
+};
+use crate::batch::{
+    arithmetic::{
+        Evaluation,
+        evaluate,
+    },
+    loops::{
+        self,
+        Bindings,
+        ForLoop,
+        MAX_ITERATIONS,
+    },
+    lexer::{
+        Blocks,
+        ExpansionScopes,
+        ScopeChange,
+        Token,
+        TokenKind,
+        scope_change,
+        tokenize,
+    },
+    MAX_LINE_LENGTH,
+};
+
+
+/// The most commands a loop is written out as, one per iteration, before it is kept as a loop instead.
+const MAX_UNROLLED: usize = 32;
+
+/// The dynamic variables cmd derives from the exit code of the last command.
+const EXIT_VARIABLES: [&str; 3] = ["errorlevel", "=ExitCode", "=ExitCodeAscii"];
+
+/// Default values of the environment variables found on a stock 64-bit Windows install, for a user named "user".
+const WINDOWS_DEFAULTS: [(&str, &str); 32] = [
+    ("ALLUSERSPROFILE", r"C:\ProgramData"),
+    ("APPDATA", r"C:\Users\user\AppData\Roaming"),
+    ("CommonProgramFiles", r"C:\Program Files\Common Files"),
+    ("CommonProgramFiles(x86)", r"C:\Program Files (x86)\Common Files"),
+    ("CommonProgramW6432", r"C:\Program Files\Common Files"),
+    ("COMPUTERNAME", "DESKTOP-WIN10"),
+    ("ComSpec", r"C:\Windows\system32\cmd.exe"),
+    ("DriverData", r"C:\Windows\System32\Drivers\DriverData"),
+    ("HOMEDRIVE", "C:"),
+    ("HOMEPATH", r"\Users\user"),
+    ("LOCALAPPDATA", r"C:\Users\user\AppData\Local"),
+    ("LOGONSERVER", r"\\DESKTOP-WIN10"),
+    ("NUMBER_OF_PROCESSORS", "4"),
+    ("OS", "Windows_NT"),
+    ("Path", r"C:\Windows\system32;C:\Windows;C:\Windows\System32\Wbem;C:\Windows\System32\WindowsPowerShell\v1.0\;C:\Users\user\AppData\Local\Microsoft\WindowsApps;"),
+    ("PATHEXT", ".COM;.EXE;.BAT;.CMD;.VBS;.VBE;.JS;.JSE;.WSF;.WSH;.MSC"),
+    ("PROCESSOR_ARCHITECTURE", "AMD64"),
+    ("PROCESSOR_LEVEL", "6"),
+    ("ProgramData", r"C:\ProgramData"),
+    ("ProgramFiles", r"C:\Program Files"),
+    ("ProgramFiles(x86)", r"C:\Program Files (x86)"),
+    ("ProgramW6432", r"C:\Program Files"),
+    ("PROMPT", "$P$G"),
+    ("PSModulePath", r"C:\Program Files\WindowsPowerShell\Modules;C:\Windows\system32\WindowsPowerShell\v1.0\Modules"),
+    ("PUBLIC", r"C:\Users\Public"),
+    ("SystemDrive", "C:"),
+    ("SystemRoot", r"C:\Windows"),
+    ("TEMP", r"C:\Users\user\AppData\Local\Temp"),
+    ("TMP", r"C:\Users\user\AppData\Local\Temp"),
+    ("USERNAME", "user"),
+    ("USERPROFILE", r"C:\Users\user"),
+    ("windir", r"C:\Windows"),
+];
+
+
+/// ### A case-insensitive table of batch variables, as cmd keeps them.<br>
+/// Values are held in source form, so constructs which cmd would only resolve at runtime, such as `%%` or `%~dp0`, are kept as written.<br>
+/// Variables assigned from outside the script describe the machine it runs on, so only slices of them are expanded, while plain references are kept.
+#[derive(Debug, Clone, Default)]
+pub struct Environment {
+    vars: HashMap<String, Entry>,
+}
+
+/// A single variable in an Environment, along with the 0-based line which last defined it, if any.
+#[derive(Debug, Clone)]
+struct Entry {
+    name: String,
+    value: String,
+    defined_at: Option<usize>,
+}
+
+impl Environment {
+
+    /// Creates a new, empty Environment.
+    pub fn new() -> Self {
+        Environment { vars: HashMap::new() }
+    }
+
+    /// Creates an Environment holding the defaults of a stock Windows install, such as *ComSpec*, *ProgramFiles* or *PUBLIC*.
+    pub fn windows_defaults() -> Self {
+        let mut environment: Environment = Environment::new();
+        for (name, value) in WINDOWS_DEFAULTS {
+            environment.set(name, value);
+        };
+
+        environment
+    }
+
+    /// Assigns every `NAME=VALUE` line of *src*, which is the format printed by a bare `set` command.<br><br>
+    /// Blank lines, lines without an `=` and lines starting with `#` are skipped, and a CRLF line ending is tolerated.
+    pub fn load(&mut self, src: &str) {
+        for line in src.lines() {
+            if line.starts_with('#') {
+                continue;
+            };
+
+            if let Some((name, value)) = line.split_once('=') {
+                if !name.is_empty() {
+                    self.set(name, value);
+                };
+            };
+        };
+    }
+
+    /// Returns the value of a variable, ignoring the case of its name.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.vars.get(&name.to_lowercase()).map(|entry| entry.value.as_str())
+    }
+
+    /// Assigns a variable, or removes it if *value* is empty, just as `set name=` does.
+    pub fn set(&mut self, name: &str, value: &str) {
+        self.define(name, value, None);
+    }
+
+    /// Removes a variable, returning its previous value.
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        self.vars.remove(&name.to_lowercase()).map(|entry| entry.value)
+    }
+
+    /// Iterates over every variable as a (name, value) pair, with names in the case they were last assigned with.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.vars.values().map(|entry| (entry.name.as_str(), entry.value.as_str()))
+    }
+
+    /// Returns the number of defined variables.
+    pub fn len(&self) -> usize {
+        self.vars.len()
+    }
+
+    /// Returns *true* if no variables are defined.
+    pub fn is_empty(&self) -> bool {
+        self.vars.is_empty()
+    }
+
+    /// Assigns a variable, remembering which line of the script did so.
+    fn define(&mut self, name: &str, value: &str, line: Option<usize>) {
+        if value.is_empty() {
+            self.vars.remove(&name.to_lowercase());
+        }else {
+            self.vars.insert(name.to_lowercase(), Entry { name: name.to_string(), value: value.to_string(), defined_at: line });
+        };
+    }
+}
+
+
+/// A variable reference which could not be expanded, because nothing defined it before it was used.<br><br>
+/// *line* is 1-based, and *span* is the byte range of the reference within that line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Unresolved {
+    pub line: usize,
+    pub span: Range<usize>,
+    pub name: String,
+}
+
+/// A `%name:old=new%` substitution which was evaluated while emulating.<br><br>
+/// *line* is 1-based, *span* is the byte range of the reference within that line, and *result* is what it expanded to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Substitution {
+    pub line: usize,
+    pub span: Range<usize>,
+    pub expression: String,
+    pub result: String,
+}
+
+/// A line which `call` made cmd expand more than once, such as `call set x=%%%a%%b%%%`.<br><br>
+/// *line* is 1-based, and *rounds* counts the first expansion along with every repeated one which changed the line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Reparse {
+    pub line: usize,
+    pub rounds: usize,
+}
+
+/// A variable assignment found while deobfuscating.<br><br>
+/// *line* is 1-based, and *used* is *true* if the assigned value was expanded anywhere.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Definition {
+    pub line: usize,
+    pub name: String,
+    pub value: String,
+    pub used: bool,
+}
+
+/// The result of emulating a script with an Emulator.
+#[derive(Debug, Clone, Default)]
+pub struct Emulated {
+    /// The script with every resolvable reference expanded, and the definitions consumed along the way removed.
+    pub code: String,
+    /// The variables defined once the whole script has been processed.
+    pub environment: Environment,
+    /// References which were left as they are, in the order they were encountered.
+    pub unresolved: Vec<Unresolved>,
+    /// Substitutions which were evaluated, in the order they were encountered.
+    pub substitutions: Vec<Substitution>,
+    /// Every `set` command which was tracked, in the order they were encountered.
+    pub definitions: Vec<Definition>,
+    /// Lines which were expanded more than once because of `call`, in the order they were encountered.
+    pub reparsed: Vec<Reparse>,
+    /// The number of references which were expanded.
+    pub expansions: usize,
+}
+
+
+/// ### Recovers cleartext from scripts built out of variable concatenation, by replaying cmd's percent expansion line by line.<br>
+/// This works regardless of the tool which produced the script, as long as its variables are assigned with `set` or `set /a` commands,
+/// or come from the exit code of a `cmd /c exit N`, and are built up by plain commands or by FOR loops over literal values.<br>
+/// ### Example Usage:<br>
+/// ```
+/// use oxidizer::batch::emulator::Emulator;
+/// 
+/// let dropper = "set x1=ec\nset x2=ho\nset x3= hi\n%x1%%X2%%x3%";
+/// let emulated = Emulator::new().run(dropper);
+/// 
+/// assert_eq!(emulated.code, "echo hi");
+/// assert!(emulated.unresolved.is_empty());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Emulator {
+    environment: Environment,
+}
+
+impl Emulator {
+
+    /// Creates a new Emulator which starts every run from an empty environment.
+    pub fn new() -> Self {
+        Emulator { environment: Environment::new() }
+    }
+
+    /// Creates a new Emulator which starts every run from a copy of *environment*.
+    pub fn with_environment(environment: Environment) -> Self {
+        Emulator { environment }
+    }
+
+    /// Returns the environment every run starts from.
+    pub fn environment(&self) -> &Environment {
+        &self.environment
+    }
+
+    /// Expands the provided script line by line, tracking `set`, `set /a`, `setlocal`, `endlocal` and `cmd /c exit` commands as it goes.<br>
+    /// FOR loops over ranges, literal lists and literal `/F` strings are evaluated, up to MAX_ITERATIONS iterations over the whole script.<br><br>
+    /// Just as in cmd, a parenthesized block, a line continued with `^` or a line of `&`-chained commands is percent-expanded as a whole before any of it runs,
+    /// so a `%name%` reference does not see a `set` made earlier in the same unit.<br>
+    /// Within scopes that enable delayed expansion, `!name!` references are expanded as each command runs, in the same order cmd uses.<br>
+    /// References to undefined variables are kept literally and recorded in *unresolved*.<br>
+    /// Lines defining a variable which was later expanded are dropped, as their content now lives where it was used,
+    /// unless a line that is kept still reads the variable by name.
+    pub fn run(&self, src: &str) -> Emulated {
+        let mut run: Run = Run::new(self.environment.clone());
+        let mut lines: Vec<Option<String>> = Vec::new();
+        let mut unit: Vec<(usize, String)> = Vec::new();
+        let mut blocks: Blocks = Blocks::new();
+        let raw_lines: Vec<&str> = src.split('\n').collect();
+
+        for (index, raw_line) in raw_lines.iter().enumerate() {
+            let line: &str = raw_line.strip_suffix('\r').unwrap_or(raw_line);
+
+            // cmd finds blocks after percent expansion, and reads the whole unit before running any of it.
+            let expanded: String = run.expand(line, index, TokenKind::Variable, false);
+            let tokens: Vec<Token> = tokenize(&expanded, false);
+            blocks.commands(&tokens);
+            let open: bool = blocks.is_open(&tokens);
+
+            unit.push((index, expanded));
+            if !open {
+                lines.extend(run.run_unit(std::mem::take(&mut unit)));
+            };
+        };
+        if !unit.is_empty() {
+            lines.extend(run.run_unit(unit));
+        };
+
+        // Carriage returns are kept out of values, but still belong to the line.
+        let lines: Vec<Option<String>> = lines.into_iter()
+            .zip(raw_lines)
+            .map(|(line, raw_line)| match raw_line.ends_with('\r') {
+                true => line.map(|line| format!("{}\r", line.replace('\n', "\r\n"))),
+                false => line,
+            })
+            .collect();
+
+        // A kept line which reads a variable by name, as `set /a` does, still needs the definition it reads.
+        let mut dropped: HashSet<usize> = run.definitions.intersection(&run.consumed).copied().collect();
+        while let Some(&(_, line)) = run.dependencies.iter().find(|(user, line)| !dropped.contains(user) && dropped.contains(line)) {
+            dropped.remove(&line);
+        };
+        for lines in &run.merged {
+            if dropped.contains(&(lines.start - 1)) {
+                dropped.extend(lines.clone());
+            };
+        };
+
+        let code: String = lines.into_iter()
+            .enumerate()
+            .filter(|(index, _)| !dropped.contains(index))
+            .filter_map(|(_, line)| line)
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        for definition in run.assignments.iter_mut() {
+            let index: usize = definition.line - 1;
+            definition.used = run.consumed.contains(&index) || run.dependencies.iter().any(|(_, line)| *line == index);
+        };
+
+        Emulated {
+            code,
+            environment: run.environment,
+            unresolved: run.unresolved,
+            substitutions: run.substitutions,
+            definitions: run.assignments,
+            reparsed: run.reparsed,
+            expansions: run.expansions,
+        }
+    }
+}
+
+
+/// The working state of a single emulation run, discarded once the output has been produced.
+struct Run {
+    environment: Environment,
+    saved: Vec<Environment>,
+    scopes: ExpansionScopes,
+    unresolved: Vec<Unresolved>,
+    substitutions: Vec<Substitution>,
+    expansions: usize,
+    assignments: Vec<Definition>,
+    reparsed: Vec<Reparse>,
+    exit_status: Option<ExitStatus>,
+    definitions: HashSet<usize>,
+    consumed: HashSet<usize>,
+    dependencies: Vec<(usize, usize)>,
+    merged: Vec<Range<usize>>,
+    iterations: usize,
+    accumulators: Vec<String>,
+    overflowed: HashSet<usize>,
+}
+
+impl Run {
+
+    fn new(environment: Environment) -> Self {
+        Run {
+            environment,
+            saved: Vec::new(),
+            scopes: ExpansionScopes::new(),
+            unresolved: Vec::new(),
+            substitutions: Vec::new(),
+            expansions: 0,
+            assignments: Vec::new(),
+            reparsed: Vec::new(),
+            exit_status: None,
+            definitions: HashSet::new(),
+            consumed: HashSet::new(),
+            dependencies: Vec::new(),
+            merged: Vec::new(),
+            iterations: 0,
+            accumulators: Vec::new(),
+            overflowed: HashSet::new(),
+        }
+    }
+
+    /// Runs a unit of lines which have already been through percent expansion together, returning what each line becomes.<br><br>
+    /// A FOR command whose body spans the unit is evaluated as a whole, and its other lines become *None* if it is written out in the first one.
+    /// Any other unit has its commands run in order, and its lines which only assign variables count as definitions.
+    fn run_unit(&mut self, unit: Vec<(usize, String)>) -> Vec<Option<String>> {
+        let start: usize = unit[0].0;
+
+        if unit.len() > 1 && starts_with_for(&unit[0].1) {
+            let command: String = collapse_percents(&unit.iter().map(|(_, line)| line.as_str()).collect::<Vec<&str>>().join("\n"));
+
+            return match self.evaluate_loop(&command, start) {
+                Some((commands, false)) if (1..=MAX_UNROLLED).contains(&commands.len()) => {
+                    let mut lines: Vec<Option<String>> = vec![None; unit.len()];
+                    lines[0] = Some(commands.join("\n"));
+                    lines
+                },
+                Some((_, assigned)) => {
+                    if assigned {
+                        self.definitions.insert(start);
+                        self.merged.push(start+1..start+unit.len());
+                    };
+                    unit.into_iter().map(|(_, line)| Some(line)).collect()
+                },
+                None if self.scopes.delayed_expansion() => unit.into_iter()
+                    .map(|(index, line)| Some(self.expand(&line, index, TokenKind::DelayedVariable, false)))
+                    .collect(),
+                None => unit.into_iter().map(|(_, line)| Some(line)).collect(),
+            };
+        };
+
+        let mut blocks: Blocks = Blocks::new();
+        unit.into_iter()
+            .map(|(index, line)| {
+                let (output, assigned, droppable): (String, bool, bool) = self.run_commands(&line, index, &mut blocks, false);
+                if assigned && droppable {
+                    self.definitions.insert(index);
+                };
+                Some(output)
+            })
+            .collect()
+    }
+
+    /// Runs every command of a line which has already been through percent expansion, in order, returning what the line becomes.<br>
+    /// Also returns whether any command assigned a variable, and whether the line holds nothing but assignments, without any block parentheses.
+    fn run_commands(&mut self, line: &str, index: usize, blocks: &mut Blocks, collapsed: bool) -> (String, bool, bool) {
+        let commands: Vec<Range<usize>> = blocks.commands(&tokenize(line, false));
+        let mut output: String = String::new();
+        let mut assigned: bool = false;
+        let mut droppable: bool = true;
+        let mut end: usize = 0;
+
+        for command in commands {
+            let separator: &str = &line[end..command.start];
+            droppable &= !separator.contains(['(', ')']);
+            output.push_str(separator);
+            end = command.end;
+
+            let text: &str = &line[command];
+            if text.trim().is_empty() {
+                output.push_str(text);
+                continue;
+            };
+
+            let (executed, assignment): (String, bool) = self.execute(text, index, collapsed);
+            output.push_str(&executed);
+            assigned |= assignment;
+            droppable &= assignment;
+        };
+        output.push_str(&line[end..]);
+
+        (output, assigned, droppable)
+    }
+
+    /// Runs every iteration of a FOR command which has been through percent expansion, returning the commands each one ran.<br>
+    /// Also returns whether the body assigned any variable.<br><br>
+    /// Returns *None*, without running anything, if the loop cannot be evaluated or would take the run past MAX_ITERATIONS.
+    fn evaluate_loop(&mut self, command: &str, index: usize) -> Option<(Vec<String>, bool)> {
+        let parsed: ForLoop = ForLoop::parse(command)?;
+        let iterations: Vec<Bindings> = parsed.iterations()?;
+        if self.iterations + iterations.len() > MAX_ITERATIONS {
+            return None;
+        };
+        self.iterations += iterations.len();
+
+        // The variable a loop builds up starts out empty, rather than being left for the environment to fill in.
+        let accumulator: Option<String> = parse_set(parsed.body).map(|(name, _)| name.to_string());
+        self.accumulators.extend(accumulator.clone());
+
+        let mut commands: Vec<String> = Vec::new();
+        let mut assigned: bool = false;
+        for bindings in iterations {
+            let (body, count): (String, usize) = loops::substitute(parsed.body, &bindings);
+            self.expansions += count;
+
+            // The body is expanded once with the loop, so its lines run one after another without seeing each other.
+            let mut blocks: Blocks = Blocks::new();
+            let ran: Vec<String> = body.split('\n')
+                .map(|line| {
+                    let (output, assignment, _): (String, bool, bool) = self.run_commands(line, index, &mut blocks, true);
+                    assigned |= assignment;
+                    output
+                })
+                .collect();
+            commands.push(ran.join("\n"));
+        };
+
+        if accumulator.is_some() {
+            self.accumulators.pop();
+        };
+        Some((commands, assigned))
+    }
+
+    /// Runs a single command which has already been through percent expansion, returning what it ran, and whether it assigned a variable.<br>
+    /// Delayed expansion still happens on every run, followed by another round of percent expansion for every `call`.<br><br>
+    /// *collapsed* is set if the command's escaped percents were already collapsed, as they are inside a loop.
+    /// A nested loop is written out one command per iteration, unless it assigns variables and is kept as their definition instead.
+    fn execute(&mut self, command: &str, index: usize, collapsed: bool) -> (String, bool) {
+        if starts_with_for(command) {
+            let collapsed_command: String = if collapsed { command.to_string() } else { collapse_percents(command) };
+            match self.evaluate_loop(&collapsed_command, index) {
+                Some((commands, false)) if (1..=MAX_UNROLLED).contains(&commands.len()) => return (commands.join("\n"), false),
+                Some((_, assigned)) => return (command.to_string(), assigned),
+                None => {},
+            };
+        };
+
+        let calls: usize = call_depth(command);
+        let mut expanded: String = match calls > 0 && !collapsed {
+            true => collapse_percents(command),
+            false => command.to_string(),
+        };
+        if self.scopes.delayed_expansion() {
+            expanded = self.expand(&expanded, index, TokenKind::DelayedVariable, false);
+        };
+
+        let mut rounds: usize = 1;
+        for _ in 0..calls {
+            let reparsed: String = self.expand_phases(&expanded, index, true);
+            if reparsed != expanded {
+                rounds += 1;
+            };
+            expanded = reparsed;
+        };
+        if rounds > 1 {
+            match self.reparsed.last_mut() {
+                Some(reparse) if reparse.line == index + 1 => reparse.rounds = reparse.rounds.max(rounds),
+                _ => self.reparsed.push(Reparse { line: index + 1, rounds }),
+            };
+        };
+
+        let assigned: bool = self.track(&expanded, index, collapsed || calls > 0);
+        (expanded, assigned)
+    }
+
+    /// Tracks the scope or variables an expanded command changes, on the 0-based line *index*, returning *true* if it assigned a variable.<br>
+    /// *collapsed* is set if the command's escaped percents were already collapsed, as they are after `call` or inside a loop.
+    fn track(&mut self, command: &str, index: usize, collapsed: bool) -> bool {
+        let tokens: Vec<Token> = tokenize(command, false);
+
+        if let Some(change) = scope_change(&tokens) {
+            self.change_scope(change);
+        }else if let Some(expression) = parse_arithmetic(command, collapsed) {
+            if let Some(evaluation) = evaluate(&expression, &self.environment) {
+                self.assign(index, evaluation);
+                return true;
+            };
+        }else if let Some((name, value)) = parse_set(command) {
+            self.record(index, name.to_string(), value.to_string());
+            return true;
+        }else if let Some(code) = parse_exit(command) {
+            // The command defines the dynamic variables cmd derives from the exit code, so it goes once they have been expanded.
+            self.exit_status = Some(ExitStatus { code, line: index });
+            return true;
+        };
+
+        false
+    }
+
+    /// Runs a line through percent expansion, and then through delayed expansion if the current scope enables it.<br>
+    /// With *collapse* set, escaped percents lose their escape, as they do when the line is about to be parsed again.
+    fn expand_phases(&mut self, line: &str, index: usize, collapse: bool) -> String {
+        let expanded: String = self.expand(line, index, TokenKind::Variable, collapse);
+
+        if self.scopes.delayed_expansion() {
+            self.expand(&expanded, index, TokenKind::DelayedVariable, false)
+        }else {
+            expanded
+        }
+    }
+
+    /// Expands every reference of the given *kind* in a line, which is either **Variable** or **DelayedVariable**.<br><br>
+    /// *index* is the 0-based line number, and spans recorded for `!name!` references, or in repeated rounds, are relative to the line as it was before that pass.<br>
+    /// With *collapse* set, `%%` becomes `%`, just as cmd does before handing a line to `call`.
+    /// A reference which would take the line past MAX_LINE_LENGTH is left unresolved, and nothing the line assigns is kept.
+    fn expand(&mut self, line: &str, index: usize, kind: TokenKind, collapse: bool) -> String {
+        let mut expanded: String = String::new();
+
+        for token in tokenize(line, kind == TokenKind::DelayedVariable) {
+            if collapse && matches!(token.kind, TokenKind::EscapedPercent | TokenKind::ForVariable) {
+                expanded.push_str(&token.text[1..]);
+                continue;
+            };
+
+            if token.kind == kind {
+                let expansion: Expansion = match expand_variable(&token, &self.environment, self.exit_status) {
+                    // cmd refuses to run a line which expands past its limit, which also stops a value doubling on every loop.
+                    Expansion::Value(value, _) if expanded.len() + value.len() > MAX_LINE_LENGTH && expanded.chars().count() + value.chars().count() > MAX_LINE_LENGTH => {
+                        self.overflowed.insert(index);
+                        Expansion::Unresolved
+                    },
+                    expansion => expansion,
+                };
+
+                match expansion {
+                    Expansion::Value(value, defined_at) => {
+                        if token.variable_modifier().is_some_and(|modifier| !modifier.starts_with('~')) {
+                            self.substitutions.push(Substitution {
+                                line: index + 1,
+                                span: token.span.clone(),
+                                expression: token.text.to_string(),
+                                result: value.clone(),
+                            });
+                        };
+
+                        expanded.push_str(&value);
+                        // A loop reading what its own earlier iterations assigned does not make its line redundant.
+                        self.consumed.extend(defined_at.filter(|line| *line != index));
+                        self.expansions += 1;
+                        continue;
+                    },
+                    Expansion::Ambient => {},
+                    Expansion::Unresolved if self.accumulators.iter().any(|name| token.variable_name().is_some_and(|variable| variable.eq_ignore_ascii_case(name))) => {
+                        self.expansions += 1;
+                        continue;
+                    },
+                    Expansion::Unresolved => self.unresolved.push(Unresolved {
+                        line: index + 1,
+                        span: token.span.clone(),
+                        name: token.variable_name().unwrap_or_default().to_string(),
+                    }),
+                };
+            };
+
+            expanded.push_str(token.text);
+        };
+
+        expanded
+    }
+
+    /// Makes the assignments of a `set /a` command on the 0-based line *index*, remembering the definitions it read by name.
+    fn assign(&mut self, index: usize, evaluation: Evaluation) {
+        for name in &evaluation.references {
+            if let Some(line) = self.environment.vars.get(&name.to_lowercase()).and_then(|entry| entry.defined_at).filter(|line| *line != index) {
+                self.dependencies.push((index, line));
+            };
+        };
+
+        for (name, value) in evaluation.assignments {
+            self.record(index, name, value.to_string());
+        };
+    }
+
+    /// Assigns a variable on the 0-based line *index*, which then counts as a definition.<br>
+    /// A variable assigned more than once by the same line, as in a loop, is only recorded with its final value.<br>
+    /// If the line expanded past MAX_LINE_LENGTH, or *value* is longer than that, the variable is undefined instead, leaving its references unresolved.
+    fn record(&mut self, index: usize, name: String, value: String) {
+        if self.overflowed.contains(&index) || value.chars().count() > MAX_LINE_LENGTH {
+            self.environment.remove(&name);
+            self.assignments.retain(|definition| definition.line != index + 1 || !definition.name.eq_ignore_ascii_case(&name));
+            return;
+        };
+
+        self.environment.define(&name, &value, Some(index));
+
+        match self.assignments.iter_mut().find(|definition| definition.line == index + 1 && definition.name.eq_ignore_ascii_case(&name)) {
+            Some(definition) => definition.value = value,
+            None => self.assignments.push(Definition { line: index + 1, name, value, used: false }),
+        };
+    }
+
+    /// Opens or closes a local scope, saving or restoring the environment just as `setlocal` and `endlocal` do.
+    fn change_scope(&mut self, change: ScopeChange) {
+        match change {
+            ScopeChange::Setlocal { .. } => self.saved.push(self.environment.clone()),
+            ScopeChange::Endlocal => {
+                if let Some(environment) = self.saved.pop() {
+                    self.environment = environment;
+                };
+            },
+        };
+
+        self.scopes.apply(change);
+    }
+}
+
+
+/// The exit code left behind by the last `cmd /c exit N`, along with the 0-based line which ran it.
+#[derive(Debug, Clone, Copy)]
+struct ExitStatus {
+    code: i32,
+    line: usize,
+}
+
+impl ExitStatus {
+
+    /// Returns *name* as one of the dynamic variables cmd derives from the exit code, if it is one with a value.<br><br>
+    /// `%=ExitCode%` is the code as 8 hexadecimal digits, while `%=ExitCodeAscii%` is the character it encodes, if that is printable.
+    fn entry(&self, name: &str) -> Option<Entry> {
+        let value: String = match name.to_lowercase().as_str() {
+            "errorlevel" => self.code.to_string(),
+            "=exitcode" => format!("{:08X}", self.code),
+            "=exitcodeascii" => match u8::try_from(self.code) {
+                Ok(byte @ b' '..=b'~') => char::from(byte).to_string(),
+                _ => return None,
+            },
+            _ => return None,
+        };
+
+        Some(Entry { name: name.to_string(), value, defined_at: Some(self.line) })
+    }
+}
+
+
+/// The outcome of expanding a single variable reference.
+enum Expansion {
+    /// The reference expands to this value, which was assigned on the given line of the script, if any.
+    Value(String, Option<usize>),
+    /// The reference names a variable from outside the script, and is kept as written.
+    Ambient,
+    /// The reference cannot be expanded.
+    Unresolved,
+}
+
+/// Expands a variable reference, applying any `~start,length` substring or `old=new` substitution modifier.<br><br>
+/// Variables the script never assigned may still be one of those cmd derives from the last *exit_status*, such as `%errorlevel%`.
+fn expand_variable(token: &Token, environment: &Environment, exit_status: Option<ExitStatus>) -> Expansion {
+    let name: &str = match token.variable_name() {
+        Some(name) => name,
+        None => return Expansion::Unresolved,
+    };
+
+    let dynamic: Option<Entry> = exit_status.and_then(|status| status.entry(name));
+    let entry: &Entry = match environment.vars.get(&name.to_lowercase()).or(dynamic.as_ref()) {
+        Some(entry) => entry,
+        // Without an exit in the script, the code is whatever ran before it left behind.
+        None if exit_status.is_none() && EXIT_VARIABLES.iter().any(|variable| variable.eq_ignore_ascii_case(name)) => return Expansion::Ambient,
+        None => return Expansion::Unresolved,
+    };
+
+    match token.variable_modifier() {
+        None if entry.defined_at.is_none() => Expansion::Ambient,
+        None => Expansion::Value(entry.value.clone(), entry.defined_at),
+        Some(modifier) => {
+            let value: Option<String> = match modifier.strip_prefix('~') {
+                Some(spec) => substring(&entry.value, spec),
+                None => substitute(&entry.value, modifier),
+            };
+
+            match value {
+                Some(value) => Expansion::Value(value, entry.defined_at),
+                None => Expansion::Unresolved,
+            }
+        },
+    }
+}
+
+/// Applies a `start,length` substring specification to *value*, following cmd's rules.<br><br>
+/// A negative *start* counts back from the end, a negative *length* stops that many characters short of the end,
+/// and an omitted *length* runs to the end. Returns *None* if either number is malformed.
+fn substring(value: &str, spec: &str) -> Option<String> {
+    let (start, length): (&str, Option<&str>) = match spec.split_once(',') {
+        Some((start, length)) => (start, Some(length)),
+        None => (spec, None),
+    };
+
+    let chars: Vec<char> = value.chars().collect();
+    let len: i64 = chars.len() as i64;
+
+    let start: i64 = parse_offset(start)?;
+    let begin: i64 = if start < 0 { (len + start).max(0) } else { start.min(len) };
+    let end: i64 = match length.map(parse_offset) {
+        None => len,
+        Some(None) => return None,
+        Some(Some(length)) if length < 0 => len + length,
+        Some(Some(length)) => (begin + length).min(len),
+    };
+
+    if end <= begin {
+        return Some(String::new());
+    };
+
+    Some(chars[begin as usize..end as usize].iter().collect())
+}
+
+/// Applies an `old=new` substitution specification to *value*, following cmd's rules.<br><br>
+/// Every occurrence of *old* is replaced, ignoring case, while the `*old=new` form replaces everything up to and including the first one.<br>
+/// Returns *None* if the specification has no `=`.
+fn substitute(value: &str, spec: &str) -> Option<String> {
+    let (old, new): (&str, &str) = spec.split_once('=')?;
+    let (old, prefix): (&str, bool) = match old.strip_prefix('*') {
+        Some(old) => (old, true),
+        None => (old, false),
+    };
+
+    if old.is_empty() {
+        return Some(value.to_string());
+    };
+
+    let chars: Vec<char> = value.chars().collect();
+    let pattern: Vec<char> = old.chars().collect();
+    let matches_at = |i: usize| -> bool {
+        i + pattern.len() <= chars.len() &&
+            chars[i..i+pattern.len()].iter().zip(&pattern).all(|(a, b)| a.to_lowercase().eq(b.to_lowercase()))
+    };
+
+    let mut result: String = String::new();
+    let mut i: usize = 0;
+    while i < chars.len() {
+        if matches_at(i) && prefix {
+            return Some(new.chars().chain(chars[i+pattern.len()..].iter().copied()).collect());
+        }else if matches_at(i) {
+            result.push_str(new);
+            i += pattern.len();
+        }else {
+            result.push(chars[i]);
+            i += 1;
+        };
+    };
+
+    // Without a match, the prefix form leaves the value untouched.
+    Some(result)
+}
+
+/// Parses an offset of a substring modifier, where an empty offset counts as zero.
+fn parse_offset(text: &str) -> Option<i64> {
+    match text.trim() {
+        "" => Some(0),
+        text => text.parse().ok(),
+    }
+}
+
+/// Collapses every `%%` of a line to `%`, as percent expansion does when it reaches them.
+fn collapse_percents(line: &str) -> String {
+    tokenize(line, false).iter()
+        .map(|token| match token.kind {
+            TokenKind::EscapedPercent | TokenKind::ForVariable => &token.text[1..],
+            _ => token.text,
+        })
+        .collect()
+}
+
+/// Returns *true* if a line starts with a FOR command.
+fn starts_with_for(line: &str) -> bool {
+    tokenize(line, false).iter()
+        .find(|token| token.kind != TokenKind::Whitespace)
+        .is_some_and(|token| token.kind == TokenKind::Text && token.text.trim_start_matches('@').eq_ignore_ascii_case("for"))
+}
+
+/// Returns the number of `call` commands a line starts with, each of which makes cmd expand the rest of it once more.
+fn call_depth(line: &str) -> usize {
+    tokenize(line, false).iter()
+        .filter(|token| token.kind != TokenKind::Whitespace)
+        .take_while(|token| token.kind == TokenKind::Text && token.text.trim_start_matches('@').eq_ignore_ascii_case("call"))
+        .count()
+}
+
+/// Returns the expression of a `set /a` command, with quotes and caret escapes removed, as cmd's arithmetic sees it.<br><br>
+/// Escaped percents such as the `%%` of a modulo are collapsed too, unless *collapsed* is set because `call` already did so.
+fn parse_arithmetic(line: &str, collapsed: bool) -> Option<String> {
+    let tokens: Vec<Token> = tokenize(line, false);
+    let mut words = tokens.iter()
+        .filter(|token| token.kind != TokenKind::Whitespace)
+        .skip_while(|token| token.kind == TokenKind::Text && token.text.trim_start_matches('@').eq_ignore_ascii_case("call"));
+
+    let keyword: &Token = words.next()?;
+    if keyword.kind != TokenKind::Text || !keyword.text.trim_start_matches('@').eq_ignore_ascii_case("set") {
+        return None;
+    };
+    let switch: &Token = words.next().filter(|token| token.kind == TokenKind::Text && token.text.get(..2).is_some_and(|switch| switch.eq_ignore_ascii_case("/a")))?;
+
+    let mut expression: String = String::new();
+    for token in tokenize(&line[switch.span.start+2..], false) {
+        match token.kind {
+            TokenKind::Operator | TokenKind::Redirection => break,
+            TokenKind::Quote => {},
+            TokenKind::Escape => expression.push_str(&token.text[1..]),
+            TokenKind::EscapedPercent | TokenKind::ForVariable if !collapsed => expression.push_str(&token.text[1..]),
+            _ => expression.push_str(token.text),
+        };
+    };
+
+    Some(expression)
+}
+
+/// Returns the exit code set by a `cmd /c exit N` command, which is how scripts produce characters through `%=ExitCodeAscii%`.<br><br>
+/// The `exit` may be quoted and may carry `/b`, while cmd may be named by path or as `%ComSpec%`. The code must be a decimal number.
+fn parse_exit(line: &str) -> Option<i32> {
+    let end: usize = tokenize(line, false).iter()
+        .find(|token| matches!(token.kind, TokenKind::Operator | TokenKind::Redirection))
+        .map_or(line.len(), |token| token.span.start);
+    let command: String = line[..end].replace('"', "");
+    let mut words = command.split_whitespace();
+
+    let program: &str = words.next()?.trim_start_matches('@');
+    let program: &str = program.rsplit('\\').next().unwrap_or(program);
+    if !["cmd", "cmd.exe", "%comspec%"].iter().any(|name| name.eq_ignore_ascii_case(program)) {
+        return None;
+    };
+    if !words.next()?.eq_ignore_ascii_case("/c") || !words.next()?.eq_ignore_ascii_case("exit") {
+        return None;
+    };
+
+    let code: &str = match words.next()? {
+        switch if switch.eq_ignore_ascii_case("/b") => words.next()?,
+        code => code,
+    };
+    if words.next().is_some() {
+        return None;
+    };
+
+    code.parse().ok()
+}
+
+/// Splits an expanded line into the name and value it assigns, if it is a plain `set name=value` command.<br><br>
+/// Both the bare and quoted `set "name=value"` forms are recognised, while `set /a` and `set /p` are not.
+/// A `call` in front of the `set` makes no difference to the assignment.
+pub(crate) fn parse_set(line: &str) -> Option<(&str, &str)> {
+    let tokens: Vec<Token> = tokenize(line, false);
+    let mut words = tokens.iter()
+        .filter(|token| token.kind != TokenKind::Whitespace)
+        .skip_while(|token| token.kind == TokenKind::Text && token.text.trim_start_matches('@').eq_ignore_ascii_case("call"));
+
+    let keyword: &Token = words.next()?;
+    if keyword.kind != TokenKind::Text || !keyword.text.trim_start_matches('@').eq_ignore_ascii_case("set") {
+        return None;
+    };
+
+    // The assignment ends where the next command or a redirection begins.
+    let end: usize = words
+        .find(|token| matches!(token.kind, TokenKind::Operator | TokenKind::Redirection))
+        .map_or(line.len(), |token| token.span.start);
+    let assignment: &str = line[keyword.span.end..end].trim_start_matches([' ', '\t']);
+
+    let assignment: &str = match assignment.strip_prefix('"') {
+        Some(quoted) => &quoted[..quoted.rfind('"')?],
+        None if assignment.starts_with('/') => return None,
+        None => assignment,
+    };
+
+    let (name, value) = assignment.split_once('=')?;
+    if name.is_empty() {
+        return None;
+    };
+
+    Some((name, value))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_environment_ignores_case() {
+        let mut environment: Environment = Environment::new();
+        environment.set("Path", "C:\\Windows");
+
+        assert_eq!(environment.get("PATH"), Some("C:\\Windows"));
+        environment.set("pAtH", "");
+        assert!(environment.is_empty());
+    }
+
+    #[test]
+    fn test_substring() {
+        let value: &str = "0123456789";
+
+        assert_eq!(substring(value, "5,1").as_deref(), Some("5"));
+        assert_eq!(substring(value, "7").as_deref(), Some("789"));
+        assert_eq!(substring(value, "-1").as_deref(), Some("9"));
+        assert_eq!(substring(value, "-3,2").as_deref(), Some("78"));
+        assert_eq!(substring(value, "2,-2").as_deref(), Some("234567"));
+        assert_eq!(substring(value, ",3").as_deref(), Some("012"));
+        assert_eq!(substring(value, "-20,2").as_deref(), Some("01"));
+        assert_eq!(substring(value, "12,3").as_deref(), Some(""));
+        assert_eq!(substring(value, "6,-6").as_deref(), Some(""));
+        assert_eq!(substring(value, "x,1"), None);
+    }
+
+    #[test]
+    fn test_substitute() {
+        assert_eq!(substitute("pXoXwXeXr", "X=").as_deref(), Some("power"));
+        assert_eq!(substitute("JUNKcmdjunk", "junk=").as_deref(), Some("cmd"));
+        assert_eq!(substitute("a.b.c", ".=-").as_deref(), Some("a-b-c"));
+        assert_eq!(substitute("garbage::calc", "*::=").as_deref(), Some("calc"));
+        assert_eq!(substitute("x=1", "*=y").as_deref(), Some("x=1"));
+        assert_eq!(substitute("no match", "*zz=").as_deref(), Some("no match"));
+        assert_eq!(substitute("value", "nothing"), None);
+    }
+
+    #[test]
+    fn test_substitutions_are_recorded() {
+        let src: &str = "set p=QQpoQQwershQQell\n%p:qq=% -c %p:*sh=%";
+
+        let emulated: Emulated = Emulator::new().run(src);
+
+        assert_eq!(emulated.code, "powershell -c QQell");
+        assert_eq!(emulated.substitutions, vec![
+            Substitution { line: 2, span: 0..7, expression: String::from("%p:qq=%"), result: String::from("powershell") },
+            Substitution { line: 2, span: 11..19, expression: String::from("%p:*sh=%"), result: String::from("QQell") },
+        ]);
+    }
+
+    #[test]
+    fn test_slicing_alphabets() {
+        let src: &str = "set abc=hcmoe lt\n%abc:~4,1%%abc:~1,1%%abc:~0,1%%abc:~-5,1%%ComSpec:~-4,1%%ComSpec:~-3%";
+
+        let emulated: Emulated = Emulator::with_environment(Environment::windows_defaults()).run(src);
+
+        assert_eq!(emulated.code, "echo.exe");
+        assert!(emulated.unresolved.is_empty());
+    }
+
+    #[test]
+    fn test_environment_overrides() {
+        let mut environment: Environment = Environment::windows_defaults();
+        environment.load("# dumped from the target\r\nUSERNAME=victim\r\nComSpec=D:\\cmd.exe\r\n");
+
+        let emulated: Emulated = Emulator::with_environment(environment).run("echo %USERNAME% %username:~0,3% %COMSPEC:~0,2%");
+
+        assert_eq!(emulated.code, "echo %USERNAME% vic D:");
+        assert!(emulated.unresolved.is_empty());
+    }
+
+    #[test]
+    fn test_delayed_expansion() {
+        let src: &str = "set a=1\necho !a!\nsetlocal EnableDelayedExpansion\nset a=2\nset b=!a!%a%\necho !b! !nope! %b%\nendlocal\necho %a%";
+
+        let emulated: Emulated = Emulator::new().run(src);
+
+        assert_eq!(emulated.code, "echo !a!\nsetlocal EnableDelayedExpansion\necho 22 !nope! 22\nendlocal\necho 1");
+        assert_eq!(emulated.unresolved, vec![Unresolved { line: 6, span: 9..15, name: String::from("nope") }]);
+    }
+
+    #[test]
+    fn test_parse_set() {
+        assert_eq!(parse_set("set name=World"), Some(("name", "World")));
+        assert_eq!(parse_set("  @SET x==& echo"), Some(("x", "=")));
+        assert_eq!(parse_set("set \"quoted=a & b\" trailing"), Some(("quoted", "a & b")));
+        assert_eq!(parse_set("set /a n=1+1"), None);
+        assert_eq!(parse_set("setlocal"), None);
+        assert_eq!(parse_set("echo set x=1"), None);
+    }
+
+    #[test]
+    fn test_foreign_layout() {
+        let src: &str = "@echo off\r\nset \"k=set\"\r\n%k% a=po\r\n%k% B=wer\r\n%k% c=shell\r\n%a%%b%%C% -nop %payload%\r\n";
+
+        let emulated: Emulated = Emulator::new().run(src);
+
+        assert_eq!(emulated.code, "@echo off\r\npowershell -nop %payload%\r\n");
+        assert_eq!(emulated.expansions, 6);
+        assert_eq!(emulated.unresolved, vec![Unresolved { line: 6, span: 15..24, name: String::from("payload") }]);
+    }
+
+    #[test]
+    fn test_unused_definitions_are_kept() {
+        let emulated: Emulated = Emulator::new().run("set greeting=hi\nset unused=1\necho %greeting%");
+
+        assert_eq!(emulated.code, "set unused=1\necho hi");
+        assert_eq!(emulated.environment.get("unused"), Some("1"));
+
+        let used: Vec<(&str, bool)> = emulated.definitions.iter().map(|definition| (definition.name.as_str(), definition.used)).collect();
+        assert_eq!(used, [("greeting", true), ("unused", false)]);
+    }
+
+    #[test]
+    fn test_call_double_expansion() {
+        let src: &str = "set name=payload\nset a=na\nset b=me\ncall set x=%%%a%%b%%%\n@call echo %%x%% %%%%\necho %x% 100%%\ncall :sub %%x%%";
+
+        let emulated: Emulated = Emulator::new().run(src);
+
+        assert_eq!(emulated.code, "@call echo payload %\necho payload 100%%\ncall :sub payload");
+        assert_eq!(emulated.reparsed, vec![
+            Reparse { line: 4, rounds: 2 },
+            Reparse { line: 5, rounds: 2 },
+            Reparse { line: 7, rounds: 2 },
+        ]);
+        assert_eq!(emulated.environment.get("x"), Some("payload"));
+        assert!(emulated.unresolved.is_empty());
+    }
+
+    #[test]
+    fn test_nested_calls() {
+        let emulated: Emulated = Emulator::new().run("set v=deep\nset p=v\ncall call echo %%%%%p%%%%%");
+
+        assert_eq!(emulated.code, "call call echo deep");
+        assert_eq!(emulated.reparsed, vec![Reparse { line: 3, rounds: 3 }]);
+    }
+
+    #[test]
+    fn test_arithmetic_assignments() {
+        let src: &str = "set /a \"a=0x60, b=a|020\"\nset /a c=b %% 3 ^<^< 2\nset /a d=a+1\necho %b% %c%\nset /a e=(7";
+
+        let emulated: Emulated = Emulator::new().run(src);
+
+        // The first line stays, as the kept "set /a d=a+1" reads it by name.
+        assert_eq!(emulated.code, "set /a \"a=0x60, b=a|020\"\nset /a d=a+1\necho 112 4\nset /a e=(7");
+        assert_eq!(emulated.environment.get("d"), Some("97"));
+        assert_eq!(emulated.environment.get("e"), None);
+        let used: Vec<(&str, &str, bool)> = emulated.definitions.iter()
+            .map(|definition| (definition.name.as_str(), definition.value.as_str(), definition.used))
+            .collect();
+        assert_eq!(used, [("a", "96", true), ("b", "112", true), ("c", "4", true), ("d", "97", false)]);
+    }
+
+    #[test]
+    fn test_exit_code_characters() {
+        let src: &str = "set /a n=0x70\ncmd /c exit %n%\nset p=%=ExitCodeAscii%\n%ComSpec% /c \"exit /b 105\"\nset i=%=ExitCodeAscii%\ncmd.exe /c exit 110\nset q=%=ExitCode:~-2%%errorlevel%\n%p%%i%ng %q%";
+
+        let emulated: Emulated = Emulator::new().run(src);
+
+        assert_eq!(emulated.code, "ping 6E110");
+        let names: Vec<&str> = emulated.unresolved.iter().map(|reference| reference.name.as_str()).collect();
+        assert_eq!(names, ["ComSpec"]);
+        assert!(emulated.definitions.iter().all(|definition| definition.used));
+    }
+
+    #[test]
+    fn test_exit_variables_before_any_exit() {
+        let emulated: Emulated = Emulator::new().run("echo %errorlevel% %=ExitCodeAscii%\ncmd /c exit 7\necho %=ExitCodeAscii%");
+
+        assert_eq!(emulated.code, "echo %errorlevel% %=ExitCodeAscii%\ncmd /c exit 7\necho %=ExitCodeAscii%");
+        assert_eq!(emulated.unresolved.len(), 1);
+        assert_eq!(emulated.unresolved[0].line, 3);
+    }
+
+    #[test]
+    fn test_loops_build_variables() {
+        let src: &str = concat!(
+            "set x=clac\n",
+            "for /L %%i in (3,-1,0) do call set r=%%r%%%%x:~%%i,1%%\n",
+            "for %%c in (\"p o\" w) do call set s=%%s%%%%~c\n",
+            "@FOR /F \"tokens=2* delims=,\" %%a in (\"x,st,art\") do (set t=%%a%%b)\n",
+            "%t% %r% %s%",
+        );
+
+        let emulated: Emulated = Emulator::new().run(src);
+
+        assert_eq!(emulated.code, "start calc p ow");
+        assert!(emulated.unresolved.is_empty());
+        let definitions: Vec<(usize, &str, &str)> = emulated.definitions.iter()
+            .map(|definition| (definition.line, definition.name.as_str(), definition.value.as_str()))
+            .collect();
+        assert_eq!(definitions, [(1, "x", "clac"), (2, "r", "calc"), (3, "s", "p ow"), (4, "t", "start")]);
+    }
+
+    #[test]
+    fn test_loops_with_delayed_expansion() {
+        let src: &str = "setlocal EnableDelayedExpansion\nset x=olleh\nfor /l %%i in (4,-1,0) do set r=!r!!x:~%%i,1!\necho !r!";
+
+        assert_eq!(Emulator::new().run(src).code, "setlocal EnableDelayedExpansion\necho hello");
+    }
+
+    #[test]
+    fn test_loops_are_unrolled() {
+        let emulated: Emulated = Emulator::new().run("set c=ca\nfor /f \"tokens=1,2\" %%a in (\"%c%lc x\") do start %%a\nfor /l %%n in (1,1,2) do ping -n %%n %%~nxz\nfor /r %%f in (*) do echo %%f");
+
+        assert_eq!(emulated.code, "start calc\nping -n 1 %~nxz\nping -n 2 %~nxz\nfor /r %%f in (*) do echo %%f");
+    }
+
+    #[test]
+    fn test_blocks_expand_before_running() {
+        let src: &str = "set x=old\nif 1==1 (\n  set x=new\n  echo %x%\n)\necho %x%";
+
+        let emulated: Emulated = Emulator::new().run(src);
+
+        assert_eq!(emulated.code, "if 1==1 (\n  echo old\n)\necho new");
+        assert!(emulated.definitions.iter().all(|definition| definition.used));
+    }
+
+    #[test]
+    fn test_chained_commands() {
+        let emulated: Emulated = Emulator::new().run("set a=1& echo %a%\nset b=2&set c=3\necho %a%%b%^\n%c%");
+
+        assert_eq!(emulated.code, "set a=1& echo %a%\necho 12^\n3");
+        assert_eq!(emulated.unresolved.len(), 1);
+        assert_eq!(emulated.unresolved[0].line, 1);
+    }
+
+    #[test]
+    fn test_values_stop_at_the_line_limit() {
+        let src: &str = "set x=ab\nfor /L %%i in (1,1,40) do call set x=%%x%%%%x%%\necho %x%";
+
+        let emulated: Emulated = Emulator::new().run(src);
+
+        assert_eq!(emulated.code, "for /L %%i in (1,1,40) do call set x=%%x%%%%x%%\necho %x%");
+        assert_eq!(emulated.environment.get("x"), None);
+        assert!(emulated.unresolved.iter().any(|reference| reference.line == 3 && reference.name == "x"));
+        assert!(!emulated.definitions.iter().any(|definition| definition.line == 2));
+    }
+
+    #[test]
+    fn test_multiline_loops() {
+        let src: &str = "setlocal EnableDelayedExpansion\r\nfor %%c in (p i n g) do (\r\n  set w=!w!%%c\r\n)\r\nfor %%n in (1 2) do (\r\n  echo %%n\r\n)\r\n!w! localhost";
+
+        assert_eq!(Emulator::new().run(src).code, "setlocal EnableDelayedExpansion\r\necho 1\r\necho 2\r\nping localhost");
+    }
+}
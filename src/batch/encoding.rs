@@ -0,0 +1,269 @@
+/* Copyright (c) 2022 Zach Griffin (0xTas)
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE. */
+
+
+use std::fmt;
+use crate::{
+    Error,
+    Result,
+};
+
+
+/// The characters of code page 437 from 0x80 to 0xFF, which cmd.exe uses by default on US installs.
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// The characters of Windows-1252 from 0x80 to 0x9F, where it differs from Latin-1. Unassigned bytes are *None*.
+const CP1252_C1: [Option<char>; 32] = [
+    Some('€'), None, Some('‚'), Some('ƒ'), Some('„'), Some('…'), Some('†'), Some('‡'),
+    Some('ˆ'), Some('‰'), Some('Š'), Some('‹'), Some('Œ'), None, Some('Ž'), None,
+    None, Some('‘'), Some('’'), Some('“'), Some('”'), Some('•'), Some('–'), Some('—'),
+    Some('˜'), Some('™'), Some('š'), Some('›'), Some('œ'), None, Some('ž'), Some('Ÿ'),
+];
+
+
+/// A text encoding which batch scripts are commonly saved in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Encoding {
+    /// UTF-8 without a byte order mark.
+    Utf8,
+    /// UTF-8 starting with the EF BB BF byte order mark, as saved by older versions of Notepad.
+    Utf8Bom,
+    /// Little-endian UTF-16 with a byte order mark, as written by PowerShell's `>` redirection.
+    Utf16Le,
+    /// Big-endian UTF-16 with a byte order mark.
+    Utf16Be,
+    /// The OEM code page cmd.exe uses by default on US installs.
+    Cp437,
+    /// The ANSI code page used by Western European editions of Windows.
+    Windows1252,
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name: &str = match self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Utf8Bom => "UTF-8 with BOM",
+            Encoding::Utf16Le => "UTF-16LE",
+            Encoding::Utf16Be => "UTF-16BE",
+            Encoding::Cp437 => "CP437",
+            Encoding::Windows1252 => "Windows-1252",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl Encoding {
+
+    /// Looks up an encoding by a case-insensitive label, such as `utf-8`, `utf-16le`, `cp437` or `1252`.
+    pub fn from_label(label: &str) -> Option<Self> {
+        let label: String = label.trim().to_lowercase().replace(['-', '_', ' '], "");
+
+        match label.as_str() {
+            "utf8" => Some(Encoding::Utf8),
+            "utf8bom" | "utf8sig" => Some(Encoding::Utf8Bom),
+            "utf16" | "utf16le" | "unicode" => Some(Encoding::Utf16Le),
+            "utf16be" => Some(Encoding::Utf16Be),
+            "cp437" | "437" | "ibm437" | "oem" => Some(Encoding::Cp437),
+            "cp1252" | "1252" | "windows1252" | "ansi" => Some(Encoding::Windows1252),
+            _ => None,
+        }
+    }
+
+    /// Returns the encoding a console switched to *code_page* with `chcp` reads, if it is one this crate can write.<br><br>
+    /// Only **437**, **1252** and **65001** (UTF-8) are supported.
+    pub fn from_code_page(code_page: u32) -> Option<Self> {
+        match code_page {
+            437 => Some(Encoding::Cp437),
+            1252 => Some(Encoding::Windows1252),
+            65001 => Some(Encoding::Utf8),
+            _ => None,
+        }
+    }
+
+    /// ### Guesses the encoding of a script from its raw bytes.<br>
+    /// Byte order marks win outright, then UTF-16 is recognised by its pattern of NUL bytes, then valid UTF-8 is assumed.<br>
+    /// Anything else is a legacy code page: CP437 if its high bytes are mostly box drawing, otherwise Windows-1252.<br>
+    /// ### Example Usage:<br>
+    /// ```
+    /// use oxidizer::batch::encoding::Encoding;
+    /// 
+    /// assert_eq!(Encoding::detect(b"\xFF\xFEe\x00c\x00h\x00o\x00"), Encoding::Utf16Le);
+    /// assert_eq!(Encoding::detect(b"echo caf\xE9"), Encoding::Windows1252);
+    /// assert_eq!(Encoding::detect("echo café".as_bytes()), Encoding::Utf8);
+    /// ```
+    pub fn detect(bytes: &[u8]) -> Self {
+        if bytes.starts_with(&[0xEF, 0xBB, 0xBF]) {
+            return Encoding::Utf8Bom;
+        }else if bytes.starts_with(&[0xFF, 0xFE]) {
+            return Encoding::Utf16Le;
+        }else if bytes.starts_with(&[0xFE, 0xFF]) {
+            return Encoding::Utf16Be;
+        };
+
+        // Batch scripts are almost entirely ASCII, so UTF-16 leaves a NUL in every other byte.
+        let sample: &[u8] = &bytes[..bytes.len().min(1024) & !1];
+        let pairs: usize = sample.len() / 2;
+        let even_nuls: usize = sample.iter().step_by(2).filter(|byte| **byte == 0).count();
+        let odd_nuls: usize = sample.iter().skip(1).step_by(2).filter(|byte| **byte == 0).count();
+        if pairs > 0 && odd_nuls * 2 > pairs && even_nuls * 10 < pairs {
+            return Encoding::Utf16Le;
+        }else if pairs > 0 && even_nuls * 2 > pairs && odd_nuls * 10 < pairs {
+            return Encoding::Utf16Be;
+        };
+
+        if std::str::from_utf8(bytes).is_ok() {
+            return Encoding::Utf8;
+        };
+
+        let high: Vec<u8> = bytes.iter().copied().filter(|byte| *byte >= 0x80).collect();
+        let box_drawing: usize = high.iter().filter(|byte| (0xB0..=0xDF).contains(*byte)).count();
+        if box_drawing * 2 > high.len() {
+            Encoding::Cp437
+        }else {
+            Encoding::Windows1252
+        }
+    }
+
+    /// Decodes raw bytes in this encoding, dropping any byte order mark. Line endings are left exactly as they are.<br><br>
+    /// Returns **Error::Decode** if the bytes are not valid in this encoding.
+    pub fn decode(self, bytes: &[u8]) -> Result<String> {
+        match self {
+            Encoding::Utf8 | Encoding::Utf8Bom => {
+                let bytes: &[u8] = bytes.strip_prefix(&[0xEF, 0xBB, 0xBF]).unwrap_or(bytes);
+                String::from_utf8(bytes.to_vec()).map_err(|_| Error::Decode(self))
+            },
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let bom: [u8; 2] = if self == Encoding::Utf16Le { [0xFF, 0xFE] } else { [0xFE, 0xFF] };
+                let bytes: &[u8] = bytes.strip_prefix(&bom).unwrap_or(bytes);
+                if !bytes.len().is_multiple_of(2) {
+                    return Err(Error::Decode(self));
+                };
+
+                let units = bytes.chunks_exact(2).map(|pair| {
+                    if self == Encoding::Utf16Le { u16::from_le_bytes([pair[0], pair[1]]) } else { u16::from_be_bytes([pair[0], pair[1]]) }
+                });
+                char::decode_utf16(units).collect::<std::result::Result<String, _>>().map_err(|_| Error::Decode(self))
+            },
+            Encoding::Cp437 => Ok(bytes.iter().map(|byte| match byte {
+                0x00..=0x7F => *byte as char,
+                _ => CP437_HIGH[(*byte - 0x80) as usize],
+            }).collect()),
+            Encoding::Windows1252 => bytes.iter().map(|byte| match byte {
+                0x80..=0x9F => CP1252_C1[(*byte - 0x80) as usize].ok_or(Error::Decode(self)),
+                _ => Ok(*byte as char),
+            }).collect(),
+        }
+    }
+
+    /// Encodes text in this encoding, writing a byte order mark for the encodings which carry one.<br><br>
+    /// Returns **Error::Encode** with the first character which cannot be represented.
+    pub fn encode(self, text: &str) -> Result<Vec<u8>> {
+        match self {
+            Encoding::Utf8 => Ok(text.as_bytes().to_vec()),
+            Encoding::Utf8Bom => Ok([&[0xEF, 0xBB, 0xBF], text.as_bytes()].concat()),
+            Encoding::Utf16Le => Ok([0xFF, 0xFE].into_iter().chain(text.encode_utf16().flat_map(u16::to_le_bytes)).collect()),
+            Encoding::Utf16Be => Ok([0xFE, 0xFF].into_iter().chain(text.encode_utf16().flat_map(u16::to_be_bytes)).collect()),
+            Encoding::Cp437 => text.chars().map(|chr| match chr {
+                '\0'..='\x7F' => Ok(chr as u8),
+                _ => CP437_HIGH.iter()
+                    .position(|high| *high == chr)
+                    .map(|index| 0x80 + index as u8)
+                    .ok_or(Error::Encode(chr, self)),
+            }).collect(),
+            Encoding::Windows1252 => text.chars().map(|chr| match chr as u32 {
+                0x00..=0x7F | 0xA0..=0xFF => Ok(chr as u8),
+                _ => CP1252_C1.iter()
+                    .position(|c1| *c1 == Some(chr))
+                    .map(|index| 0x80 + index as u8)
+                    .ok_or(Error::Encode(chr, self)),
+            }).collect(),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(Encoding::detect(b"\xEF\xBB\xBFecho"), Encoding::Utf8Bom);
+        assert_eq!(Encoding::detect(b"\xFE\xFF\x00e"), Encoding::Utf16Be);
+        assert_eq!(Encoding::detect(b"e\x00c\x00h\x00o\x00\r\x00\n\x00"), Encoding::Utf16Le);
+        assert_eq!(Encoding::detect(b"\x00e\x00c\x00h\x00o"), Encoding::Utf16Be);
+        assert_eq!(Encoding::detect(b"echo \xC9\xCD\xCD\xBB"), Encoding::Cp437);
+        assert_eq!(Encoding::detect(b"echo \x80 5"), Encoding::Windows1252);
+        assert_eq!(Encoding::detect(b""), Encoding::Utf8);
+    }
+
+    #[test]
+    fn test_roundtrip_preserves_line_endings() {
+        let text: &str = "@echo off\r\necho Größe ± 5°\nrem mixed endings\r\n";
+
+        for encoding in [Encoding::Utf8, Encoding::Utf8Bom, Encoding::Utf16Le, Encoding::Utf16Be, Encoding::Cp437, Encoding::Windows1252] {
+            let bytes: Vec<u8> = encoding.encode(text).unwrap();
+
+            // Legacy code pages cannot be told apart without box drawing characters, which Windows-1252 lacks.
+            if !matches!(encoding, Encoding::Cp437 | Encoding::Windows1252) {
+                assert_eq!(Encoding::detect(&bytes), encoding, "{}", encoding);
+            };
+            assert_eq!(encoding.decode(&bytes).unwrap(), text, "{}", encoding);
+        };
+    }
+
+    #[test]
+    fn test_code_pages() {
+        assert_eq!(Encoding::Cp437.decode(b"\xC9\xCD\xBB \x9B").unwrap(), "╔═╗ ¢");
+        assert_eq!(Encoding::Windows1252.decode(b"\x80 caf\xE9").unwrap(), "€ café");
+        assert!(matches!(Encoding::Windows1252.decode(b"\x81"), Err(Error::Decode(Encoding::Windows1252))));
+        assert!(matches!(Encoding::Cp437.encode("€"), Err(Error::Encode('€', Encoding::Cp437))));
+        assert!(matches!(Encoding::Utf8.decode(b"caf\xE9"), Err(Error::Decode(Encoding::Utf8))));
+    }
+
+    #[test]
+    fn test_from_label() {
+        assert_eq!(Encoding::from_label("UTF-16LE"), Some(Encoding::Utf16Le));
+        assert_eq!(Encoding::from_label("cp437"), Some(Encoding::Cp437));
+        assert_eq!(Encoding::from_label("windows-1252"), Some(Encoding::Windows1252));
+        assert_eq!(Encoding::from_label("utf-8-bom"), Some(Encoding::Utf8Bom));
+        assert_eq!(Encoding::from_label("latin-9"), None);
+    }
+
+    #[test]
+    fn test_from_code_page() {
+        assert_eq!(Encoding::from_code_page(437), Some(Encoding::Cp437));
+        assert_eq!(Encoding::from_code_page(1252), Some(Encoding::Windows1252));
+        assert_eq!(Encoding::from_code_page(65001), Some(Encoding::Utf8));
+        assert_eq!(Encoding::from_code_page(850), None);
+        assert_eq!(Encoding::Cp437.encode("é").unwrap(), b"\x82");
+    }
+}
@@ -0,0 +1,636 @@
+/* Copyright (c) 2022 Zach Griffin (0xTas)
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE. */
+
+
+use std::ops::Range;
+
+
+/// The kind of construct a token represents in cmd's syntax.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    /// A run of ordinary characters with no special meaning to cmd.
+    Text,
+    /// A run of spaces, tabs or carriage returns.
+    Whitespace,
+    /// A `%name%` reference, including any `:~start,len` or `:old=new` modifier.
+    Variable,
+    /// A batch argument reference such as `%1`, `%*` or `%~dp0`.
+    Argument,
+    /// A FOR loop variable such as `%%i`, including any `~` modifiers as in `%%~nxi`.
+    ForVariable,
+    /// A `!name!` reference, only recognised when delayed expansion is enabled.
+    DelayedVariable,
+    /// A `%%` which collapses to a literal percent sign.
+    EscapedPercent,
+    /// A lone `%` with nothing to pair up with.
+    Percent,
+    /// A caret escape such as `^&`, or a trailing `^` which continues the line.
+    Escape,
+    /// A double quote, which toggles whether operators are treated literally.
+    Quote,
+    /// A redirection such as `>`, `>>`, `<`, `2>` or `2>&1`.
+    Redirection,
+    /// A command separator or pipe: `&`, `&&`, `||` or `|`.
+    Operator,
+    /// An opening parenthesis, which starts a block.
+    OpenParen,
+    /// A closing parenthesis, which ends a block.
+    CloseParen,
+    /// A `:label` line, running to the end of the line apart from any percent constructs.
+    Label,
+    /// A `rem` or `::` comment, running to the end of the line apart from any percent constructs.
+    Comment,
+}
+
+/// A single token of a batch line, along with the text and byte span it covers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token<'a> {
+    pub kind: TokenKind,
+    pub text: &'a str,
+    pub span: Range<usize>,
+}
+
+impl<'a> Token<'a> {
+
+    /// Returns the name referenced by a **Variable** or **DelayedVariable** token, without delimiters or modifiers.
+    pub fn variable_name(&self) -> Option<&'a str> {
+        let inner: &str = match self.kind {
+            TokenKind::Variable => self.text.strip_prefix('%')?.strip_suffix('%')?,
+            TokenKind::DelayedVariable => self.text.strip_prefix('!')?.strip_suffix('!')?,
+            _ => return None,
+        };
+
+        Some(inner.split(':').next().unwrap_or(inner))
+    }
+
+    /// Returns the modifier of a **Variable** or **DelayedVariable** token, such as `~5,1` or `old=new`.
+    pub fn variable_modifier(&self) -> Option<&'a str> {
+        let inner: &str = match self.kind {
+            TokenKind::Variable => self.text.strip_prefix('%')?.strip_suffix('%')?,
+            TokenKind::DelayedVariable => self.text.strip_prefix('!')?.strip_suffix('!')?,
+            _ => return None,
+        };
+
+        inner.split_once(':').map(|(_, modifier)| modifier)
+    }
+
+    /// Returns *true* for tokens which cmd replaces during percent expansion.
+    pub fn is_percent_construct(&self) -> bool {
+        matches!(self.kind,
+            TokenKind::Variable | TokenKind::Argument | TokenKind::ForVariable |
+            TokenKind::EscapedPercent | TokenKind::Percent)
+    }
+}
+
+
+/// A change to cmd's local environment made by a `setlocal` or `endlocal` command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScopeChange {
+    /// `setlocal`, which opens a new scope and may switch delayed expansion on or off within it.
+    Setlocal { delayed_expansion: Option<bool> },
+    /// `endlocal`, which returns to the scope in effect before the matching `setlocal`.
+    Endlocal,
+}
+
+/// Tracks whether delayed expansion is enabled, through nested `setlocal`/`endlocal` scopes.
+#[derive(Debug, Clone, Default)]
+pub struct ExpansionScopes {
+    saved: Vec<bool>,
+    delayed_expansion: bool,
+}
+
+impl ExpansionScopes {
+
+    /// Creates a new tracker, starting with delayed expansion off as it is in a fresh cmd.exe.
+    pub fn new() -> Self {
+        ExpansionScopes { saved: Vec::new(), delayed_expansion: false }
+    }
+
+    /// Returns *true* if delayed expansion is enabled in the current scope.
+    pub fn delayed_expansion(&self) -> bool {
+        self.delayed_expansion
+    }
+
+    /// Opens or closes a scope. An `endlocal` without a matching `setlocal` is ignored, just as cmd ignores it.
+    pub fn apply(&mut self, change: ScopeChange) {
+        match change {
+            ScopeChange::Setlocal { delayed_expansion } => {
+                self.saved.push(self.delayed_expansion);
+                self.delayed_expansion = delayed_expansion.unwrap_or(self.delayed_expansion);
+            },
+            ScopeChange::Endlocal => {
+                if let Some(delayed_expansion) = self.saved.pop() {
+                    self.delayed_expansion = delayed_expansion;
+                };
+            },
+        };
+    }
+}
+
+/// How far a FOR command's parentheses have been read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ForParens {
+    Header,
+    Set,
+    Body,
+}
+
+/// ### Tracks the parenthesized blocks cmd reads a script in, and splits each line into its commands.<br>
+/// cmd reads every line of an open block, and every line continued with a trailing `^`, before it runs any of them.<br>
+/// That whole unit is percent-expanded up front, so a `set` inside it is not seen by `%name%` references in the same unit.<br>
+/// Lines should be fed in order, after percent expansion, since cmd finds blocks in the expanded text.<br><br>
+/// ### Example Usage:<br>
+/// ```
+/// use oxidizer::batch::lexer::{Blocks, tokenize};
+/// 
+/// let mut blocks = Blocks::new();
+/// let line = "if defined x (set y=1 & echo %y%";
+/// 
+/// let commands: Vec<&str> = blocks.commands(&tokenize(line, false)).into_iter().map(|command| &line[command]).collect();
+/// 
+/// assert_eq!(commands, vec!["if defined x ", "set y=1 ", " echo %y%"]);
+/// assert_eq!(blocks.depth(), 1);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Blocks {
+    depth: usize,
+}
+
+impl Blocks {
+
+    /// Creates a new tracker, outside of any block.
+    pub fn new() -> Self {
+        Blocks { depth: 0 }
+    }
+
+    /// Returns the number of blocks left open by the lines seen so far.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Returns *true* if a unit is still open after the lines seen so far, and cmd would keep reading.
+    pub fn is_open(&self, tokens: &[Token]) -> bool {
+        self.depth > 0 || continues(tokens)
+    }
+
+    /// Returns the byte ranges of the commands in a tokenized line, updating the open block depth as it goes.<br>
+    /// Commands are split at `&`, `&&`, `||` and `|`, and at parentheses which open or close a block.<br>
+    /// A FOR command runs to the end of the line, since its set and body are part of the command itself.
+    /// Operators after an `if` or `else` are left in place too, since everything after them is conditional.
+    pub fn commands(&mut self, tokens: &[Token]) -> Vec<Range<usize>> {
+        let end: usize = tokens.last().map_or(0, |token| token.span.end);
+        let mut commands: Vec<Range<usize>> = Vec::new();
+        let mut start: usize = 0;
+        let mut command_start: bool = true;
+        let mut conditional: bool = false;
+        let mut for_parens: Option<ForParens> = None;
+
+        for (i, token) in tokens.iter().enumerate() {
+            let spaced: bool = i > 0 && tokens[i-1].kind == TokenKind::Whitespace;
+
+            let separator: bool = match (token.kind, for_parens) {
+                (TokenKind::Whitespace, _) => false,
+                (TokenKind::OpenParen, Some(ForParens::Header)) => {
+                    for_parens = Some(ForParens::Set);
+                    false
+                },
+                (TokenKind::CloseParen, Some(ForParens::Set)) => {
+                    for_parens = Some(ForParens::Body);
+                    false
+                },
+                (TokenKind::OpenParen, Some(ForParens::Body)) => {
+                    self.depth += 1;
+                    false
+                },
+                (TokenKind::CloseParen, Some(ForParens::Body)) => {
+                    self.depth = self.depth.saturating_sub(1);
+                    false
+                },
+                (_, Some(_)) => false,
+                (TokenKind::Operator, None) => !conditional,
+                (TokenKind::OpenParen, None) if command_start || (conditional && spaced) => {
+                    self.depth += 1;
+                    true
+                },
+                (TokenKind::CloseParen, None) if self.depth > 0 => {
+                    self.depth -= 1;
+                    true
+                },
+                (TokenKind::Text, None) if command_start => {
+                    let word: &str = token.text.trim_start_matches('@');
+                    if word.eq_ignore_ascii_case("for") {
+                        for_parens = Some(ForParens::Header);
+                    };
+                    conditional = word.eq_ignore_ascii_case("if") || word.eq_ignore_ascii_case("else");
+                    command_start = false;
+                    false
+                },
+                _ => {
+                    command_start = false;
+                    false
+                },
+            };
+
+            if separator {
+                commands.push(start..token.span.start);
+                start = token.span.end;
+                command_start = true;
+                conditional = false;
+            };
+        };
+        commands.push(start..end.max(start));
+
+        commands
+    }
+}
+
+
+/// ### Splits a single line of batch source into tokens.<br>
+/// Percent constructs are recognised first and regardless of quoting, just as cmd expands them before parsing anything else.<br>
+/// `!name!` references are only recognised when *delayed_expansion* is enabled.<br>
+/// Concatenating the text of every token always reproduces the input line.<br>
+/// ### Example Usage:<br>
+/// ```
+/// use oxidizer::batch::lexer::{tokenize, TokenKind};
+/// 
+/// let tokens = tokenize("echo %USERNAME% > out.txt", false);
+/// 
+/// assert_eq!(tokens[2].kind, TokenKind::Variable);
+/// assert_eq!(tokens[2].variable_name(), Some("USERNAME"));
+/// assert_eq!(tokens[4].kind, TokenKind::Redirection);
+/// ```
+pub fn tokenize(line: &str, delayed_expansion: bool) -> Vec<Token<'_>> {
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut pos: usize = 0;
+    let mut in_quotes: bool = false;
+    let mut command_start: bool = true;
+
+    // Leading whitespace, then labels and "::" comments take up the whole line.
+    let body: &str = line.trim_start_matches([' ', '\t', '@']);
+    if body.starts_with(':') {
+        let start: usize = line.len() - body.len();
+        if start > 0 {
+            tokens.push(token(line, TokenKind::Whitespace, 0..start));
+        };
+        let kind: TokenKind = if body.starts_with("::") { TokenKind::Comment } else { TokenKind::Label };
+        push_remainder(&mut tokens, line, start, kind);
+
+        return tokens;
+    };
+
+    while pos < line.len() {
+        let rest: &str = &line[pos..];
+        let c: char = rest.chars().next().expect("pos should be within the line");
+
+        let (kind, len): (TokenKind, usize) = match c {
+            '%' => lex_percent(rest),
+            '!' if delayed_expansion => match rest[1..].find('!') {
+                Some(end) if end > 0 => (TokenKind::DelayedVariable, end + 2),
+                _ => (TokenKind::Text, 1),
+            },
+            '"' => {
+                in_quotes = !in_quotes;
+                (TokenKind::Quote, 1)
+            },
+            _ if in_quotes => (TokenKind::Text, text_len(rest, true, delayed_expansion)),
+            '^' => match rest[1..].chars().next() {
+                Some(next) if next != '%' => (TokenKind::Escape, 1 + next.len_utf8()),
+                _ => (TokenKind::Escape, 1),
+            },
+            '&' | '|' => {
+                command_start = true;
+                if rest[1..].starts_with(c) { (TokenKind::Operator, 2) } else { (TokenKind::Operator, 1) }
+            },
+            '<' => (TokenKind::Redirection, 1),
+            '>' => (TokenKind::Redirection, redirection_len(rest)),
+            '0'..='9' if rest[1..].starts_with('>') && at_word_boundary(line, pos) => {
+                (TokenKind::Redirection, 1 + redirection_len(&rest[1..]))
+            },
+            '(' => {
+                command_start = true;
+                (TokenKind::OpenParen, 1)
+            },
+            ')' => (TokenKind::CloseParen, 1),
+            ' ' | '\t' | '\r' => (TokenKind::Whitespace, rest.len() - rest.trim_start_matches([' ', '\t', '\r']).len()),
+            _ => {
+                let len: usize = text_len(rest, false, delayed_expansion);
+                let word: &str = rest[..len].trim_start_matches('@');
+
+                // "rem" only starts a comment in the position of a command.
+                if command_start && word.eq_ignore_ascii_case("rem") &&
+                    rest[len..].chars().next().is_none_or(|next| next == ' ' || next == '\t' || next == '\r')
+                {
+                    push_remainder(&mut tokens, line, pos, TokenKind::Comment);
+                    return tokens;
+                }else {
+                    command_start = false;
+                    (TokenKind::Text, len)
+                }
+            },
+        };
+
+        tokens.push(token(line, kind, pos..pos+len));
+        pos += len;
+    };
+
+    tokens
+}
+
+/// Returns the scope change made by a tokenized line, if it starts with a `setlocal` or `endlocal` command.
+pub fn scope_change(tokens: &[Token]) -> Option<ScopeChange> {
+    let mut words = tokens.iter().filter(|token| token.kind != TokenKind::Whitespace);
+    let command: &str = words.next().filter(|token| token.kind == TokenKind::Text)?.text.trim_start_matches('@');
+
+    if command.eq_ignore_ascii_case("endlocal") {
+        return Some(ScopeChange::Endlocal);
+    }else if !command.eq_ignore_ascii_case("setlocal") {
+        return None;
+    };
+
+    // Later arguments win, and the command ends at the first operator.
+    let mut delayed_expansion: Option<bool> = None;
+    for token in words.take_while(|token| token.kind == TokenKind::Text) {
+        if token.text.eq_ignore_ascii_case("EnableDelayedExpansion") {
+            delayed_expansion = Some(true);
+        }else if token.text.eq_ignore_ascii_case("DisableDelayedExpansion") {
+            delayed_expansion = Some(false);
+        };
+    };
+
+    Some(ScopeChange::Setlocal { delayed_expansion })
+}
+
+/// Returns *true* if a tokenized line ends with a lone `^`, which continues it onto the next line.
+pub fn continues(tokens: &[Token]) -> bool {
+    tokens.last().is_some_and(|token| token.kind == TokenKind::Escape && token.text.trim_end_matches('\r') == "^")
+}
+
+/// Tokenizes everything from *start* to the end of *line* as *kind*, apart from percent constructs, which cmd still expands there.
+fn push_remainder<'a>(tokens: &mut Vec<Token<'a>>, line: &'a str, start: usize, kind: TokenKind) {
+    let mut pos: usize = start;
+
+    while pos < line.len() {
+        let rest: &str = &line[pos..];
+        let (kind, len): (TokenKind, usize) = match rest.find('%') {
+            Some(0) => lex_percent(rest),
+            Some(end) => (kind, end),
+            None => (kind, rest.len()),
+        };
+
+        tokens.push(token(line, kind, pos..pos+len));
+        pos += len;
+    };
+}
+
+/// Builds a token covering *span* of *line*.
+fn token(line: &str, kind: TokenKind, span: Range<usize>) -> Token<'_> {
+    Token { kind, text: &line[span.clone()], span }
+}
+
+/// Lexes the percent construct at the start of *rest*, returning its kind and byte length.
+fn lex_percent(rest: &str) -> (TokenKind, usize) {
+    let after: &str = &rest[1..];
+
+    match after.chars().next() {
+        Some('%') => match after[1..].chars().next() {
+            Some(var) if var.is_ascii_alphabetic() => (TokenKind::ForVariable, 3),
+            Some('~') => match modified_reference_len(&after[2..], |c| c.is_ascii_alphabetic()) {
+                Some(len) => (TokenKind::ForVariable, 3 + len),
+                None => (TokenKind::EscapedPercent, 2),
+            },
+            _ => (TokenKind::EscapedPercent, 2),
+        },
+        Some('0'..='9') | Some('*') => (TokenKind::Argument, 2),
+        Some('~') => match modified_reference_len(&after[1..], |c| c.is_ascii_digit()) {
+            Some(len) => (TokenKind::Argument, 2 + len),
+            None => lex_variable(rest),
+        },
+        Some(_) => lex_variable(rest),
+        None => (TokenKind::Percent, 1),
+    }
+}
+
+/// Returns the byte length of the `~` modifiers at the start of *rest*, including the argument or FOR variable they apply to.<br><br>
+/// Modifiers are any of `fdpnxsatz`, optionally followed by a `$NAME:` search, as in `~nx`, `~$PATH:` or `~dp$PATH:`.
+/// A trailing modifier letter doubles as the reference itself when nothing else follows, as in `%%~nf`.
+fn modified_reference_len(rest: &str, is_reference: fn(char) -> bool) -> Option<usize> {
+    let modifiers: usize = rest.len() - rest.trim_start_matches(|c: char| "fdpnxsatzFDPNXSATZ".contains(c)).len();
+    let after: &str = &rest[modifiers..];
+
+    if let Some(search) = after.strip_prefix('$') {
+        let colon: usize = search.find(':')?;
+        let reference: char = search[colon+1..].chars().next().filter(|c| is_reference(*c))?;
+
+        return Some(modifiers + colon + 2 + reference.len_utf8());
+    };
+
+    match after.chars().next() {
+        Some(reference) if is_reference(reference) => Some(modifiers + reference.len_utf8()),
+        _ if modifiers > 0 && rest[..modifiers].chars().next_back().is_some_and(is_reference) => Some(modifiers),
+        _ => None,
+    }
+}
+
+/// Lexes a `%name%` reference at the start of *rest*, or a lone percent sign if it is never closed.
+fn lex_variable(rest: &str) -> (TokenKind, usize) {
+    match rest[1..].find('%') {
+        Some(end) => (TokenKind::Variable, end + 2),
+        None => (TokenKind::Percent, 1),
+    }
+}
+
+/// Returns the byte length of the run of ordinary text at the start of *rest*.
+fn text_len(rest: &str, in_quotes: bool, delayed_expansion: bool) -> usize {
+    let special = |c: char| -> bool {
+        match c {
+            '%' | '"' => true,
+            '!' => delayed_expansion,
+            _ if in_quotes => false,
+            '^' | '&' | '|' | '<' | '>' | '(' | ')' | ' ' | '\t' | '\r' => true,
+            _ => false,
+        }
+    };
+
+    rest.char_indices()
+        .skip(1)
+        .find(|(_, c)| special(*c))
+        .map_or(rest.len(), |(i, _)| i)
+}
+
+/// Returns the byte length of a `>`/`>>` redirection at the start of *rest*, including any `&n` handle duplication.
+fn redirection_len(rest: &str) -> usize {
+    let len: usize = if rest.starts_with(">>") { 2 } else { 1 };
+
+    match rest[len..].strip_prefix('&').and_then(|dup| dup.chars().next()) {
+        Some('0'..='9') => len + 2,
+        _ => len,
+    }
+}
+
+/// Returns *true* if the character at *pos* begins a new word, so that a digit there may be a redirection handle.
+fn at_word_boundary(line: &str, pos: usize) -> bool {
+    line[..pos].chars().next_back().is_none_or(|prev| matches!(prev, ' ' | '\t' | '&' | '|' | '(' | ')'))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tokenizes a line and returns each token's kind alongside its text.
+    fn lex(line: &str, delayed_expansion: bool) -> Vec<(TokenKind, &str)> {
+        tokenize(line, delayed_expansion).into_iter().map(|token| (token.kind, token.text)).collect()
+    }
+
+    #[test]
+    fn test_percent_constructs() {
+        assert_eq!(lex("%1%~dp0%*%%i%%%path:~0,3%%", false), vec![
+            (TokenKind::Argument, "%1"),
+            (TokenKind::Argument, "%~dp0"),
+            (TokenKind::Argument, "%*"),
+            (TokenKind::ForVariable, "%%i"),
+            (TokenKind::EscapedPercent, "%%"),
+            (TokenKind::Variable, "%path:~0,3%"),
+            (TokenKind::Percent, "%"),
+        ]);
+
+        let tokens: Vec<Token> = tokenize("%str:a=b%", false);
+        assert_eq!(tokens[0].variable_name(), Some("str"));
+        assert_eq!(tokens[0].variable_modifier(), Some("a=b"));
+    }
+
+    #[test]
+    fn test_modifiers() {
+        assert_eq!(lex("%%~nxi%%~dp$PATH:f%%~nf %~$PATH:1%~DPNX0%%~", false), vec![
+            (TokenKind::ForVariable, "%%~nxi"),
+            (TokenKind::ForVariable, "%%~dp$PATH:f"),
+            (TokenKind::ForVariable, "%%~nf"),
+            (TokenKind::Whitespace, " "),
+            (TokenKind::Argument, "%~$PATH:1"),
+            (TokenKind::Argument, "%~DPNX0"),
+            (TokenKind::EscapedPercent, "%%"),
+            (TokenKind::Text, "~"),
+        ]);
+    }
+
+    #[test]
+    fn test_operators_and_redirections() {
+        assert_eq!(lex("(dir 2>&1 | find \"a&b\") && echo^&", false), vec![
+            (TokenKind::OpenParen, "("),
+            (TokenKind::Text, "dir"),
+            (TokenKind::Whitespace, " "),
+            (TokenKind::Redirection, "2>&1"),
+            (TokenKind::Whitespace, " "),
+            (TokenKind::Operator, "|"),
+            (TokenKind::Whitespace, " "),
+            (TokenKind::Text, "find"),
+            (TokenKind::Whitespace, " "),
+            (TokenKind::Quote, "\""),
+            (TokenKind::Text, "a&b"),
+            (TokenKind::Quote, "\""),
+            (TokenKind::CloseParen, ")"),
+            (TokenKind::Whitespace, " "),
+            (TokenKind::Operator, "&&"),
+            (TokenKind::Whitespace, " "),
+            (TokenKind::Text, "echo"),
+            (TokenKind::Escape, "^&"),
+        ]);
+    }
+
+    #[test]
+    fn test_labels_and_comments() {
+        assert_eq!(lex("  :loop", false), vec![(TokenKind::Whitespace, "  "), (TokenKind::Label, ":loop")]);
+        assert_eq!(lex(":: %still% expanded", false), vec![
+            (TokenKind::Comment, ":: "),
+            (TokenKind::Variable, "%still%"),
+            (TokenKind::Comment, " expanded"),
+        ]);
+        assert_eq!(lex("@REM note & more", false), vec![(TokenKind::Comment, "@REM note & more")]);
+        assert_eq!(lex("echo rem", false), vec![
+            (TokenKind::Text, "echo"),
+            (TokenKind::Whitespace, " "),
+            (TokenKind::Text, "rem"),
+        ]);
+    }
+
+    #[test]
+    fn test_delayed_expansion() {
+        assert_eq!(lex("echo !var!", false), vec![
+            (TokenKind::Text, "echo"),
+            (TokenKind::Whitespace, " "),
+            (TokenKind::Text, "!var!"),
+        ]);
+        assert_eq!(lex("echo !var!", true)[2], (TokenKind::DelayedVariable, "!var!"));
+    }
+
+    #[test]
+    fn test_expansion_scopes() {
+        let mut scopes: ExpansionScopes = ExpansionScopes::new();
+        let lines: [&str; 6] = ["@SETLOCAL enabledelayedexpansion", "setlocal", "setlocal DisableDelayedExpansion", "endlocal", "echo setlocal", "endlocal"];
+
+        let delayed: Vec<bool> = lines.iter()
+            .map(|line| {
+                if let Some(change) = scope_change(&tokenize(line, false)) {
+                    scopes.apply(change);
+                };
+                scopes.delayed_expansion()
+            })
+            .collect();
+
+        assert_eq!(delayed, vec![true, true, false, true, true, true]);
+
+        scopes.apply(ScopeChange::Endlocal);
+        scopes.apply(ScopeChange::Endlocal);
+        assert!(!scopes.delayed_expansion());
+    }
+
+    #[test]
+    fn test_blocks() {
+        fn split<'a>(blocks: &mut Blocks, line: &'a str) -> Vec<&'a str> {
+            blocks.commands(&tokenize(line, false)).into_iter().map(|command| &line[command]).collect()
+        }
+        let mut blocks: Blocks = Blocks::new();
+
+        assert_eq!(split(&mut blocks, "set a=1&set b=2 && echo %a%"), vec!["set a=1", "set b=2 ", " echo %a%"]);
+        assert_eq!(split(&mut blocks, "if \"%a%\"==\"1\" (echo yes & set c=3"), vec!["if \"%a%\"==\"1\" ", "echo yes ", " set c=3"]);
+        assert_eq!(blocks.depth(), 1);
+        assert_eq!(split(&mut blocks, ") else (echo no)"), vec!["", " else ", "echo no", ""]);
+        assert_eq!(blocks.depth(), 0);
+        assert_eq!(split(&mut blocks, "for %%i in (a b) do (echo %%i & set x=%%i"), vec!["for %%i in (a b) do (echo %%i & set x=%%i"]);
+        assert_eq!(blocks.depth(), 1);
+        assert_eq!(split(&mut blocks, ")"), vec!["", ""]);
+        assert_eq!(split(&mut blocks, "if 1==1 echo a & echo b"), vec!["if 1==1 echo a & echo b"]);
+        assert_eq!(blocks.depth(), 0);
+
+        assert!(continues(&tokenize("echo a ^\r", false)));
+        assert!(!continues(&tokenize("echo a ^^", false)));
+        assert!(blocks.is_open(&tokenize("set x=1^", false)));
+    }
+
+    #[test]
+    fn test_tokens_cover_the_line() {
+        for line in ["echo 100%% done > \"C:\\out file.txt\"", "for %%f in (*.txt) do type %%f^\r", "é%~nx1 ^%x%"] {
+            let tokens: Vec<Token> = tokenize(line, true);
+
+            assert_eq!(tokens.iter().map(|token| token.text).collect::<String>(), line);
+            assert!(tokens.windows(2).all(|pair| pair[0].span.end == pair[1].span.start));
+        };
+    }
+}
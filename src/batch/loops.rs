@@ -0,0 +1,483 @@
+/* Copyright (c) 2022 Zach Griffin (0xTas)
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE. */
+
+
+
+/// The most iterations evaluated over a whole script, so that a hostile loop cannot stall the emulator.
+pub const MAX_ITERATIONS: usize = 10_000;
+
+/// Options which `for /F` accepts, any one of which may follow a space inside `delims=`.
+const OPTION_KEYWORDS: [&str; 5] = ["delims=", "tokens=", "skip=", "eol=", "usebackq"];
+
+/// Characters which separate the items of a plain or `/L` FOR set.
+const SET_DELIMITERS: [char; 5] = [' ', '\t', ',', ';', '='];
+
+/// Letters which may follow `~` in a FOR variable reference, as in `%~nxi`.
+const MODIFIERS: &str = "fdpnxsatzFDPNXSATZ";
+
+
+/// The values a loop gives its variables for a single iteration, as (variable, value) pairs.
+pub type Bindings = Vec<(char, String)>;
+
+/// The kinds of FOR loop which can be evaluated without touching the file system or running anything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopKind {
+    /// A plain `for %i in (a b c)`, over a literal list.
+    List,
+    /// `for /L %i in (start,step,end)`, over a range of numbers.
+    Range,
+    /// `for /F "options" %i in ("text")`, over the tokens of a literal string.
+    Tokens,
+}
+
+
+/// ### A single FOR command, as cmd sees it once percent expansion has collapsed `%%i` to `%i`.<br>
+/// Only the loops listed in LoopKind can be parsed; `/D`, `/R`, file sets and `/F` over files or command output cannot.<br>
+/// ### Example Usage:<br>
+/// ```
+/// use oxidizer::batch::loops::{
+///     ForLoop,
+///     substitute,
+/// };
+/// 
+/// let command = ForLoop::parse("for /f \"tokens=2,3 delims=.\" %a in (\"x.ca.lc\") do %a%b").unwrap();
+/// let iterations = command.iterations().unwrap();
+/// 
+/// assert_eq!(iterations, [vec![('a', "ca".to_string()), ('b', "lc".to_string())]]);
+/// assert_eq!(substitute(command.body, &iterations[0]), ("calc".to_string(), 2));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForLoop<'a> {
+    pub kind: LoopKind,
+    /// The quoted options of a `/F` loop, without their quotes.
+    pub options: &'a str,
+    pub variable: char,
+    /// Everything between the parentheses after `in`.
+    pub set: &'a str,
+    /// The command after `do`, without the parentheses of a block on the same line.
+    pub body: &'a str,
+}
+
+impl<'a> ForLoop<'a> {
+
+    /// Parses a FOR command, returning *None* if *command* is not one, or is a kind which cannot be evaluated.
+    pub fn parse(command: &'a str) -> Option<Self> {
+        let rest: &str = keyword(command.trim_start_matches([' ', '\t', '@']), "for")?;
+
+        let (kind, rest): (LoopKind, &str) = match rest.get(..2) {
+            Some(switch) if switch.eq_ignore_ascii_case("/l") => (LoopKind::Range, rest[2..].trim_start()),
+            Some(switch) if switch.eq_ignore_ascii_case("/f") => (LoopKind::Tokens, rest[2..].trim_start()),
+            _ if rest.starts_with('/') => return None,
+            _ => (LoopKind::List, rest),
+        };
+
+        let (options, rest): (&str, &str) = match rest.strip_prefix('"') {
+            Some(quoted) if kind == LoopKind::Tokens => {
+                let end: usize = quoted.find('"')?;
+                (&quoted[..end], quoted[end+1..].trim_start())
+            },
+            _ => ("", rest),
+        };
+
+        let mut chars = rest.strip_prefix('%')?.chars();
+        let variable: char = chars.next().filter(|c| !c.is_whitespace())?;
+        let rest: &str = keyword_argument(chars.as_str())?;
+
+        let rest: &str = keyword(rest, "in")?.strip_prefix('(')?;
+        let mut in_quotes: bool = false;
+        let end: usize = rest.find(|c: char| {
+            in_quotes ^= c == '"';
+            c == ')' && !in_quotes
+        })?;
+        let set: &str = &rest[..end];
+
+        let body: &str = keyword(rest[end+1..].trim_start(), "do")?.trim();
+        let body: &str = match body.strip_prefix('(') {
+            Some(block) => block.strip_suffix(')')?.trim(),
+            None => body,
+        };
+
+        Some(ForLoop { kind, options, variable, set, body })
+    }
+
+    /// Returns the values the loop gives its variables on every iteration, in order.<br><br>
+    /// Returns *None* for sets which name files or run commands, for malformed options, and for loops over more than MAX_ITERATIONS values.
+    pub fn iterations(&self) -> Option<Vec<Bindings>> {
+        match self.kind {
+            LoopKind::List => {
+                let items: Vec<&str> = split_set(self.set);
+                if items.iter().any(|item| item.contains(['*', '?'])) || items.len() > MAX_ITERATIONS {
+                    return None;
+                };
+
+                Some(items.into_iter().map(|item| vec![(self.variable, item.to_string())]).collect())
+            },
+            LoopKind::Range => {
+                let numbers: Vec<i64> = split_set(self.set).into_iter()
+                    .map(|number| number.parse::<i32>().ok().map(i64::from))
+                    .collect::<Option<Vec<i64>>>()?;
+                let number = |n: usize| -> i64 { numbers.get(n).copied().unwrap_or(0) };
+                let (start, step, end): (i64, i64, i64) = (number(0), number(1), number(2));
+
+                // A zero step never reaches the end, and cmd loops forever.
+                let count: i64 = match step {
+                    0 => return None,
+                    _ if (end - start).signum() == -step.signum() => 0,
+                    _ => (end - start) / step + 1,
+                };
+                if numbers.len() > 3 || count as usize > MAX_ITERATIONS {
+                    return None;
+                };
+
+                Some((0..count).map(|n| vec![(self.variable, (start + n * step).to_string())]).collect())
+            },
+            LoopKind::Tokens => {
+                let options: TokenOptions = TokenOptions::parse(self.options)?;
+                let quote: char = if options.usebackq { '\'' } else { '"' };
+                let text: &str = self.set.trim().strip_prefix(quote)?.strip_suffix(quote)?;
+
+                Some(text.lines()
+                    .skip(options.skip)
+                    .filter(|line| !line.is_empty() && options.eol.is_none_or(|eol| !line.starts_with(eol)))
+                    .filter_map(|line| options.split(line, self.variable))
+                    .collect())
+            },
+        }
+    }
+}
+
+
+/// The options of a `for /F` loop, which decide how each line is split into tokens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct TokenOptions {
+    delims: Vec<char>,
+    tokens: Vec<usize>,
+    remainder: bool,
+    skip: usize,
+    eol: Option<char>,
+    usebackq: bool,
+}
+
+impl TokenOptions {
+
+    /// Parses the text between the quotes of a `/F` loop, returning *None* for an option cmd would reject.
+    fn parse(text: &str) -> Option<Self> {
+        let mut options: TokenOptions = TokenOptions {
+            delims: vec![' ', '\t'],
+            tokens: vec![1],
+            remainder: false,
+            skip: 0,
+            eol: Some(';'),
+            usebackq: false,
+        };
+
+        let mut rest: &str = text.trim_start();
+        while !rest.is_empty() {
+            if let Some(value) = strip_prefix_ignore_case(rest, "delims=") {
+                // Delimiters run to the end, unless a space is followed by another option.
+                let end: usize = value.char_indices()
+                    .find(|(i, c)| *c == ' ' && starts_with_option(value[i+1..].trim_start()))
+                    .map_or(value.len(), |(i, _)| i);
+                options.delims = value[..end].chars().collect();
+                rest = &value[end..];
+            }else if let Some(value) = strip_prefix_ignore_case(rest, "tokens=") {
+                let end: usize = value.find(' ').unwrap_or(value.len());
+                (options.tokens, options.remainder) = parse_tokens(&value[..end])?;
+                rest = &value[end..];
+            }else if let Some(value) = strip_prefix_ignore_case(rest, "skip=") {
+                let end: usize = value.find(' ').unwrap_or(value.len());
+                options.skip = value[..end].parse().ok()?;
+                rest = &value[end..];
+            }else if let Some(value) = strip_prefix_ignore_case(rest, "eol=") {
+                options.eol = value.chars().next();
+                rest = &value[options.eol.map_or(0, char::len_utf8)..];
+            }else if let Some(value) = strip_prefix_ignore_case(rest, "usebackq") {
+                options.usebackq = true;
+                rest = value;
+            }else {
+                return None;
+            };
+
+            rest = rest.trim_start();
+        };
+
+        Some(options)
+    }
+
+    /// Splits a line into the tokens these options select, bound to consecutive variables from *variable* onwards.<br><br>
+    /// Returns *None* if the first variable would be left without a value, in which case cmd skips the line.
+    fn split(&self, line: &str, variable: char) -> Option<Bindings> {
+        let mut fields: Vec<(usize, usize)> = Vec::new();
+        let mut start: Option<usize> = None;
+        for (i, c) in line.char_indices().chain([(line.len(), self.delims.first().copied().unwrap_or(' '))]) {
+            match (start, i == line.len() || self.delims.contains(&c)) {
+                (Some(begin), true) => {
+                    fields.push((begin, i));
+                    start = None;
+                },
+                (None, false) => start = Some(i),
+                _ => {},
+            };
+        };
+
+        let letter = |n: usize| -> Option<char> { char::from_u32(variable as u32 + n as u32) };
+        let mut bindings: Bindings = Vec::new();
+        for (n, token) in self.tokens.iter().enumerate() {
+            if let Some((begin, end)) = fields.get(token - 1) {
+                bindings.push((letter(n)?, line[*begin..*end].to_string()));
+            };
+        };
+
+        // "*" takes everything after the last numbered token, without the delimiters in front of it.
+        if self.remainder {
+            let after: usize = match self.tokens.last() {
+                Some(last) => fields.get(last - 1).map_or(line.len(), |(_, end)| *end),
+                None => 0,
+            };
+            let remainder: &str = line[after..].trim_start_matches(self.delims.as_slice());
+            if !remainder.is_empty() {
+                bindings.push((letter(self.tokens.len())?, remainder.to_string()));
+            };
+        };
+
+        bindings.first().filter(|(first, _)| *first == variable)?;
+        Some(bindings)
+    }
+}
+
+
+/// Replaces every reference to a bound loop variable in *body*, such as `%i` or `%~nxi`, returning the result and the number of references replaced.<br><br>
+/// Variables are case-sensitive, and references to anything unbound are left as they are.
+/// Path modifiers work on the value as written, as the files it names are not looked up.
+pub fn substitute(body: &str, bindings: &[(char, String)]) -> (String, usize) {
+    let lookup = |variable: char| -> Option<&str> {
+        bindings.iter().find(|(bound, _)| *bound == variable).map(|(_, value)| value.as_str())
+    };
+
+    let mut result: String = String::new();
+    let mut count: usize = 0;
+    let mut rest: &str = body;
+
+    while let Some(pos) = rest.find('%') {
+        result.push_str(&rest[..pos]);
+        rest = &rest[pos+1..];
+
+        match reference(rest, lookup) {
+            Some((value, len)) => {
+                result.push_str(&value);
+                count += 1;
+                rest = &rest[len..];
+            },
+            None => result.push('%'),
+        };
+    };
+    result.push_str(rest);
+
+    (result, count)
+}
+
+/// Resolves the loop variable reference which follows a `%`, returning its value and the byte length of the reference after the `%`.
+fn reference<'a>(rest: &str, lookup: impl Fn(char) -> Option<&'a str>) -> Option<(String, usize)> {
+    let modified: &str = match rest.strip_prefix('~') {
+        Some(modified) => modified,
+        None => {
+            let variable: char = rest.chars().next()?;
+            return lookup(variable).map(|value| (value.to_string(), variable.len_utf8()));
+        },
+    };
+
+    let modifiers: &str = &modified[..modified.len() - modified.trim_start_matches(|c: char| MODIFIERS.contains(c)).len()];
+    let after: &str = &modified[modifiers.len()..];
+    let (search, after): (bool, &str) = match after.strip_prefix('$') {
+        Some(search) => (true, &search[search.find(':')?+1..]),
+        None => (false, after),
+    };
+
+    match after.chars().next().and_then(|variable| Some((variable, lookup(variable)?))) {
+        Some((variable, value)) => Some((modify(value, modifiers, search), rest.len() - after.len() + variable.len_utf8())),
+        // The last modifier letter doubles as the variable when nothing else matches, as in `%~nx` for a loop over x.
+        None if !search && !modifiers.is_empty() => {
+            let variable: char = modifiers.chars().next_back()?;
+            let value: &str = lookup(variable)?;
+            Some((modify(value, &modifiers[..modifiers.len()-1], false), 1 + modifiers.len()))
+        },
+        None => None,
+    }
+}
+
+/// Applies `~` modifiers to a loop value: quotes are always removed, and `d`, `p`, `n` and `x` select parts of it as a path.<br><br>
+/// A `$PATH:` search or `a`, `t` and `z` alone ask about files, which are never found, and so expand to nothing.
+fn modify(value: &str, modifiers: &str, search: bool) -> String {
+    let value: &str = value.strip_prefix('"').unwrap_or(value);
+    let value: &str = value.strip_suffix('"').unwrap_or(value);
+    let modifiers: String = modifiers.to_ascii_lowercase();
+
+    if search || (!modifiers.is_empty() && !modifiers.contains(['f', 'd', 'p', 'n', 'x', 's'])) {
+        return String::new();
+    }else if !modifiers.contains(['d', 'p', 'n', 'x']) {
+        return value.to_string();
+    };
+
+    let drive: &str = if value.as_bytes().get(1) == Some(&b':') { &value[..2] } else { "" };
+    let path: &str = &value[drive.len()..];
+    let (directory, file): (&str, &str) = path.split_at(path.rfind(['\\', '/']).map_or(0, |i| i + 1));
+    let (name, extension): (&str, &str) = file.split_at(file.rfind('.').unwrap_or(file.len()));
+
+    [('d', drive), ('p', directory), ('n', name), ('x', extension)].iter()
+        .filter(|(modifier, _)| modifiers.contains(*modifier))
+        .map(|(_, part)| *part)
+        .collect()
+}
+
+/// Splits a plain or `/L` FOR set into its items, keeping quoted items together along with their quotes.
+fn split_set(set: &str) -> Vec<&str> {
+    let mut items: Vec<&str> = Vec::new();
+    let mut start: Option<usize> = None;
+    let mut in_quotes: bool = false;
+
+    for (i, c) in set.char_indices() {
+        let separator: bool = !in_quotes && SET_DELIMITERS.contains(&c);
+        in_quotes ^= c == '"';
+        match start {
+            Some(begin) if separator => {
+                items.push(&set[begin..i]);
+                start = None;
+            },
+            None if !separator => start = Some(i),
+            _ => {},
+        };
+    };
+    if let Some(begin) = start {
+        items.push(&set[begin..]);
+    };
+
+    items
+}
+
+/// Parses a `tokens=` specification such as `1,3-5*`, returning the sorted token numbers and whether `*` asks for the remainder.
+fn parse_tokens(spec: &str) -> Option<(Vec<usize>, bool)> {
+    let (spec, remainder): (&str, bool) = match spec.strip_suffix('*') {
+        Some(spec) => (spec.trim_end_matches(','), true),
+        None => (spec, false),
+    };
+
+    let mut tokens: Vec<usize> = Vec::new();
+    for part in spec.split(',').filter(|part| !part.is_empty()) {
+        let (first, last): (usize, usize) = match part.split_once('-') {
+            Some((first, last)) => (first.parse().ok()?, last.parse().ok()?),
+            None => (part.parse().ok()?, part.parse().ok()?),
+        };
+        if first == 0 || last > 31 {
+            return None;
+        };
+        tokens.extend(first..=last);
+    };
+    tokens.sort_unstable();
+    tokens.dedup();
+
+    Some((tokens, remainder))
+}
+
+/// Returns the rest of *text* after *word*, which must be followed by whitespace or an opening parenthesis.
+fn keyword<'a>(text: &'a str, word: &str) -> Option<&'a str> {
+    keyword_argument(strip_prefix_ignore_case(text, word)?)
+}
+
+/// Returns *rest* without its leading whitespace, which must be there unless a parenthesis follows instead.
+fn keyword_argument(rest: &str) -> Option<&str> {
+    match rest.chars().next() {
+        Some(' ' | '\t') => Some(rest.trim_start()),
+        Some('(') => Some(rest),
+        _ => None,
+    }
+}
+
+/// Strips *prefix* from the start of *text*, ignoring case.
+fn strip_prefix_ignore_case<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+    text.get(..prefix.len())
+        .filter(|start| start.eq_ignore_ascii_case(prefix))
+        .map(|_| &text[prefix.len()..])
+}
+
+/// Returns *true* if *text* starts with one of the options of a `/F` loop.
+fn starts_with_option(text: &str) -> bool {
+    OPTION_KEYWORDS.iter().any(|option| strip_prefix_ignore_case(text, option).is_some())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(command: &str) -> Option<Vec<String>> {
+        ForLoop::parse(command)?.iterations().map(|iterations| {
+            iterations.into_iter()
+                .map(|bindings| bindings.into_iter().map(|(_, value)| value).collect::<Vec<String>>().join("|"))
+                .collect()
+        })
+    }
+
+    #[test]
+    fn test_parse() {
+        let parsed: ForLoop = ForLoop::parse("@FOR /L %i IN (1,1,3) DO (call set r=%r%%i)").unwrap();
+
+        assert_eq!(parsed.kind, LoopKind::Range);
+        assert_eq!((parsed.variable, parsed.set, parsed.body), ('i', "1,1,3", "call set r=%r%%i"));
+        assert_eq!(ForLoop::parse("for /f \"delims=)\" %a in (\"a)b\") do echo %a").unwrap().set, "\"a)b\"");
+        assert!(ForLoop::parse("for /d %i in (*) do echo %i").is_none());
+        assert!(ForLoop::parse("format c:").is_none());
+        assert!(ForLoop::parse("for %i in (a) echo %i").is_none());
+    }
+
+    #[test]
+    fn test_ranges_and_lists() {
+        assert_eq!(values("for /l %i in (5,-2,0) do echo %i").unwrap(), ["5", "3", "1"]);
+        assert_eq!(values("for /l %i in (1 1 3) do echo %i").unwrap(), ["1", "2", "3"]);
+        assert_eq!(values("for /l %i in (3,1,1) do echo %i").unwrap(), Vec::<String>::new());
+        assert!(values("for /l %i in (1,0,3) do echo %i").is_none());
+        assert!(values("for /l %i in (0,1,100000) do echo %i").is_none());
+        assert_eq!(values("for %a in (c;a,\"l c\" =x) do echo %a").unwrap(), ["c", "a", "\"l c\"", "x"]);
+        assert!(values("for %a in (*.txt) do echo %a").is_none());
+    }
+
+    #[test]
+    fn test_token_options() {
+        assert_eq!(values("for /f %a in (\"  first second\") do echo %a").unwrap(), ["first"]);
+        assert_eq!(values("for /f \"tokens=1,3* delims=, \" %a in (\"a, b,c d  e\") do echo %a").unwrap(), ["a|c|d  e"]);
+        assert_eq!(values("for /f \"tokens=* delims=\" %a in (\"  keep all\") do echo %a").unwrap(), ["  keep all"]);
+        assert_eq!(values("for /f \"usebackq tokens=2-3\" %a in ('x y z') do echo %a").unwrap(), ["y|z"]);
+        assert_eq!(values("for /f \"skip=1\" %a in (\"x\") do echo %a").unwrap(), Vec::<String>::new());
+        assert_eq!(values("for /f %a in (\";comment\") do echo %a").unwrap(), Vec::<String>::new());
+        assert_eq!(values("for /f \"tokens=3\" %a in (\"x y\") do echo %a").unwrap(), Vec::<String>::new());
+        assert!(values("for /f %a in ('whoami') do echo %a").is_none());
+        assert!(values("for /f %a in (file.txt) do echo %a").is_none());
+        assert!(values("for /f \"bogus=1\" %a in (\"x\") do echo %a").is_none());
+    }
+
+    #[test]
+    fn test_substitute_modifiers() {
+        let bindings: Bindings = vec![('i', "\"C:\\Tools\\run me.exe\"".to_string()), ('x', "3".to_string())];
+
+        assert_eq!(substitute("echo %i %~i", &bindings).0, "echo \"C:\\Tools\\run me.exe\" C:\\Tools\\run me.exe");
+        assert_eq!(substitute("%~dpi|%~nxi|%~xi", &bindings).0, "C:\\Tools\\|run me.exe|.exe");
+        assert_eq!(substitute("%~nx %~zi %~$PATH:i", &bindings).0, "3  ");
+        assert_eq!(substitute("%r% %X %x%", &bindings), ("%r% %X 3%".to_string(), 1));
+    }
+}
@@ -0,0 +1,339 @@
+/* Copyright (c) 2022 Zach Griffin (0xTas)
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE. */
+
+
+
+use std::ops::Range;
+use crate::batch::lexer::{
+    Token,
+    TokenKind,
+    tokenize,
+};
+
+
+/// Internal commands which print or store their arguments as written, so quotes in them are never noise.
+const LITERAL_COMMANDS: [&str; 3] = ["echo", "set", "title"];
+
+
+/// ### Strips caret-escape and quote noise from a script, following cmd's own parsing rules at every `cmd /c` nesting depth.<br>
+/// A caret only counts as noise in front of an ordinary character, where cmd drops it without changing anything.<br>
+/// Escapes that matter, such as `^&` in echo text or the doubled carets a nested `cmd /c` needs, are kept as they are.<br>
+/// Empty `""` pairs inside words are dropped, and quotes are removed from command names, except where the quotes are printed.<br>
+/// ### Example Usage:<br>
+/// ```
+/// use oxidizer::batch::normalize::normalize;
+/// 
+/// assert_eq!(normalize("p^o^w^e^r^s^h^e^l^l -c \"I\"\"EX\""), "powershell -c \"I\"\"EX\"");
+/// assert_eq!(normalize("p\"\"ow\"\"er\"\"shell -nop"), "powershell -nop");
+/// assert_eq!(normalize("cmd /c \"e^c^h^o 1 ^& 2\""), "cmd /c \"echo 1 ^& 2\"");
+/// assert_eq!(normalize("cmd /c e^^c^^h^^o^^ 1 ^^^& 2"), "cmd /c echo^^ 1 ^^^& 2");
+/// ```
+pub fn normalize(src: &str) -> String {
+    src.split('\n')
+        .map(|line| match line.strip_suffix('\r') {
+            Some(body) => format!("{}\r", normalize_command(body)),
+            None => normalize_command(line),
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Normalizes a single command line, as it is seen by one instance of cmd.
+fn normalize_command(text: &str) -> String {
+    let tokens: Vec<Token> = tokenize(text, false);
+
+    // Labels and comments are never parsed any further, while variable references are left to the emulator.
+    if tokens.iter().find(|token| token.kind != TokenKind::Whitespace).is_some_and(|token| matches!(token.kind, TokenKind::Label | TokenKind::Comment)) {
+        return text.to_string();
+    };
+    let opaque: Vec<Range<usize>> = tokens.iter()
+        .filter(|token| token.is_percent_construct() || token.kind == TokenKind::Comment)
+        .map(|token| token.span.clone())
+        .collect();
+
+    let mut normalized: String = String::new();
+    let mut pos: usize = 0;
+    let mut in_quotes: bool = false;
+    let mut command_pos: bool = true;
+    let mut literal: bool = false;
+
+    while pos < text.len() {
+        if let Some(range) = opaque.iter().find(|range| range.start == pos) {
+            normalized.push_str(&text[range.clone()]);
+            pos = range.end;
+            command_pos = false;
+            continue;
+        };
+
+        let rest: &str = &text[pos..];
+        let c: char = rest.chars().next().expect("pos should be within the line");
+
+        if in_quotes {
+            in_quotes = c != '"';
+            normalized.push(c);
+            pos += c.len_utf8();
+            continue;
+        };
+
+        match c {
+            ' ' | '\t' | '@' if command_pos => {},
+            '&' | '|' | '(' => {
+                command_pos = true;
+                literal = false;
+            },
+            ' ' | '\t' | '<' | '>' | ')' => {},
+            _ if command_pos => {
+                command_pos = false;
+                let end: usize = pos + word_len(rest);
+
+                // Command names which hide nothing but ordinary characters are written out plainly.
+                let Some(name) = plain_word(&text[pos..end]).filter(|_| !opaque.iter().any(|range| range.start < end && range.end > pos)) else {
+                    continue;
+                };
+                normalized.push_str(&name);
+                pos = end;
+                literal = LITERAL_COMMANDS.iter().any(|command| name.eq_ignore_ascii_case(command));
+
+                if is_cmd(&name) {
+                    pos = normalize_cmd_arguments(text, pos, &mut normalized);
+                };
+                continue;
+            },
+            '^' => {
+                let run: usize = rest.len() - rest.trim_start_matches('^').len();
+                pos += run;
+
+                match rest[run..].chars().next() {
+                    Some(next) if !is_significant(next) => normalized.push_str(&"^".repeat(run / 2 * 2)),
+                    // An odd run escapes the character after it, which must not be mistaken for an operator or quote.
+                    Some(next) if run % 2 == 1 => {
+                        normalized.push_str(&"^".repeat(run));
+                        normalized.push(next);
+                        pos += next.len_utf8();
+                    },
+                    _ => normalized.push_str(&"^".repeat(run)),
+                };
+                continue;
+            },
+            '"' if !literal && rest.starts_with("\"\"") && is_inside_word(&normalized, &rest[2..]) => {
+                pos += 2;
+                continue;
+            },
+            '"' => in_quotes = true,
+            _ => {},
+        };
+
+        normalized.push(c);
+        pos += c.len_utf8();
+    };
+
+    normalized
+}
+
+/// Normalizes the switches and the command which follow a `cmd` command name at *pos*, and returns the position after them.<br><br>
+/// The command after `/c` (or `/k`) is parsed once more by the inner cmd, so it is normalized on its own and then escaped again for this one.
+fn normalize_cmd_arguments(text: &str, mut pos: usize, normalized: &mut String) -> usize {
+    loop {
+        let rest: &str = &text[pos..];
+        let gap: usize = rest.len() - rest.trim_start_matches([' ', '\t']).len();
+        let end: usize = pos + gap + word_len(&rest[gap..]);
+
+        let switch: String = match plain_word(&text[pos+gap..end]) {
+            Some(switch) if switch.starts_with('/') => switch,
+            _ => return pos,
+        };
+        normalized.push_str(&text[pos..pos+gap]);
+        normalized.push_str(&switch);
+        pos = end;
+
+        if ["/c", "/k", "/r"].iter().any(|command| switch.eq_ignore_ascii_case(command)) {
+            break;
+        };
+    };
+
+    let rest: &str = &text[pos..];
+    let gap: usize = rest.len() - rest.trim_start_matches([' ', '\t']).len();
+    normalized.push_str(&rest[..gap]);
+    pos += gap;
+
+    let end: usize = pos + command_len(&text[pos..]);
+    let argument: &str = text[pos..end].trim_end_matches([' ', '\t']);
+    let inner: String = caret_pass(argument);
+
+    // cmd /c drops the quotes around a quoted command before running it.
+    let quoted: Option<&str> = inner.strip_prefix('"').and_then(|inner| inner.strip_suffix('"'));
+    let escaped: String = match quoted {
+        Some(inner) => escape_for_parent(&normalize_command(inner), true),
+        None => escape_for_parent(&normalize_command(&inner), false),
+    };
+
+    normalized.push_str(&escaped);
+    pos + argument.len()
+}
+
+/// Applies the caret phase of a single cmd parse to *text*: outside of quotes, every caret escapes the character after it and is dropped.
+fn caret_pass(text: &str) -> String {
+    let mut passed: String = String::new();
+    let mut in_quotes: bool = false;
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '^' if !in_quotes => {
+                passed.extend(chars.next());
+                continue;
+            },
+            _ => {},
+        };
+        passed.push(c);
+    };
+
+    passed
+}
+
+/// Escapes *text* so that one cmd parse turns it back into *text*, optionally wrapping it in quotes.
+fn escape_for_parent(text: &str, quoted: bool) -> String {
+    let mut escaped: String = String::new();
+    let mut in_quotes: bool = quoted;
+
+    if quoted {
+        escaped.push('"');
+    };
+    for c in text.chars() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '^' | '&' | '|' | '<' | '>' | '(' | ')' if !in_quotes => escaped.push('^'),
+            _ => {},
+        };
+        escaped.push(c);
+    };
+    if quoted {
+        escaped.push('"');
+    };
+
+    escaped
+}
+
+/// Returns the byte length of the word at the start of *text*, which runs to the first unquoted and unescaped delimiter.
+fn word_len(text: &str) -> usize {
+    let mut in_quotes: bool = false;
+    let mut chars = text.char_indices();
+
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '^' if !in_quotes => {
+                chars.next();
+            },
+            ' ' | '\t' | '&' | '|' | '<' | '>' | '(' | ')' if !in_quotes => return index,
+            _ => {},
+        };
+    };
+
+    text.len()
+}
+
+/// Returns the byte length of the command at the start of *text*, which runs to the first unquoted and unescaped `&` or `|`.
+fn command_len(text: &str) -> usize {
+    let mut in_quotes: bool = false;
+    let mut chars = text.char_indices();
+
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '^' if !in_quotes => {
+                chars.next();
+            },
+            '&' | '|' if !in_quotes => return index,
+            _ => {},
+        };
+    };
+
+    text.len()
+}
+
+/// Returns what cmd makes of a word, if escapes and quotes only ever wrap ordinary characters in it.
+fn plain_word(word: &str) -> Option<String> {
+    let plain: String = caret_pass(word).replace('"', "");
+
+    if plain.is_empty() || plain.chars().any(is_significant) {
+        None
+    }else {
+        Some(plain)
+    }
+}
+
+/// Returns *true* if a command name runs cmd itself.
+fn is_cmd(name: &str) -> bool {
+    let name: String = name.to_lowercase();
+    let file: &str = name.rsplit(['\\', '/']).next().unwrap_or(&name);
+
+    file == "cmd" || file == "cmd.exe"
+}
+
+/// Returns *true* if an empty `""` pair sits between two ordinary characters of a word.
+fn is_inside_word(before: &str, after: &str) -> bool {
+    let is_word_char = |c: char| !c.is_whitespace() && !is_significant(c);
+
+    before.chars().next_back().is_some_and(is_word_char) && after.chars().next().is_some_and(is_word_char)
+}
+
+/// Returns *true* for characters whose escapes change how cmd parses a line, which makes the carets in front of them meaningful.
+fn is_significant(c: char) -> bool {
+    matches!(c, '&' | '|' | '<' | '>' | '(' | ')' | '^' | '"' | '%' | '!' | ' ' | '\t' | '\r' | '=' | ',' | ';')
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_caret_noise() {
+        assert_eq!(normalize("e^c^h^o h^e^l^l^o"), "echo hello");
+        assert_eq!(normalize("echo a ^& b ^| c ^^ d ^> e"), "echo a ^& b ^| c ^^ d ^> e");
+        assert_eq!(normalize("echo ^^^a \"q^u^o^t^e^d\""), "echo ^^a \"q^u^o^t^e^d\"");
+        assert_eq!(normalize("echo continued ^"), "echo continued ^");
+        assert_eq!(normalize("echo %v^a% ^%x^%"), "echo %v^a% ^%x^%");
+        assert_eq!(normalize("echo ^\"p\"\"s^\" ^&c\"\"md"), "echo ^\"p\"\"s^\" ^&c\"\"md");
+    }
+
+    #[test]
+    fn test_quote_noise() {
+        assert_eq!(normalize("p\"ow\"er\"\"she\"ll\" -c \"\"i\"\"ex\"\""), "powershell -c \"\"iex\"\"");
+        assert_eq!(normalize("echo p\"\"ow\"\"ershell & \"C:\\Program Files\\x.exe\" \"\""), "echo p\"\"ow\"\"ershell & \"C:\\Program Files\\x.exe\" \"\"");
+        assert_eq!(normalize("@ s^e\"\"t x=1"), "@ set x=1");
+    }
+
+    #[test]
+    fn test_nested_cmd() {
+        // Each nesting level doubles the escaping, so the same noise looks different at every depth.
+        assert_eq!(normalize("c^m^d /V:ON /^c p^^o^^w^^e^^r^^s^^h^^e^^l^^l"), "cmd /V:ON /c powershell");
+        assert_eq!(normalize("cmd /c cmd /c p^^^^o^^^^w^^^^e^^^^r ^^^^^^^& calc & echo ^& done"), "cmd /c cmd /c power ^^^^^^^& calc & echo ^& done");
+        assert_eq!(normalize("C:\\Windows\\System32\\cmd.exe /c \"p\"\"ow\"\"ershell ^& calc\"\r"), "C:\\Windows\\System32\\cmd.exe /c \"powershell ^& calc\"\r");
+    }
+
+    #[test]
+    fn test_caret_pass() {
+        assert_eq!(caret_pass("a^b^^c^&\"^d\"^"), "ab^c&\"^d\"");
+        assert_eq!(escape_for_parent("a ^& \"b&\" c", false), "a ^^^& \"b&\" c");
+    }
+}
@@ -18,298 +18,1007 @@ LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 SOFTWARE. */
 
-
 use regex::Regex;
+use rand::{
+    RngCore,
+    SeedableRng,
+    rngs::StdRng,
+};
 use std::{
-    fs::File,
     io::Write,
-    process::exit,
     collections::{
         HashMap,
         HashSet
     }
 };
 use crate::{
-    input,
+    Error,
+    Result,
     batch::{
         CharSet,
-        generate_random_chars
+        MAX_LINE_LENGTH,
+        generate_random_chars,
+        name_space,
+        lexer::{
+            Blocks,
+            ExpansionScopes,
+            ScopeChange,
+            Token,
+            TokenKind,
+            scope_change,
+            tokenize,
+        },
+        diagnostic::{
+            Diagnostic,
+            DiagnosticKind,
+            DiagnosticPolicy,
+        },
+        encoding::Encoding,
     }
 };
 
 
+/// Base64-encoded watermarks to assist any potential deobfuscation attempts in the wild.
+const WATERMARKS: [&str; 2] = [
+    ":: VGhpcyBmaWxlIHdhcyBvYmZ1c2NhdGVkIHZpYSBodHRwczovL2dpdGh1Yi5jb20vMHhUYXMvMHhpZGl6M3I=",
+    ":: VGhpcyBmaWxlIGNhbiBiZSBwcm9ncmFtYXRpY2FsbHkgZGVvYmZ1c2NhdGVkIHZpYSBodHRwczovL2dpdGh1Yi5jb20vMHhUYXMvMHhpZGl6M3I=",
+];
+
+
+/// Decides what the obfuscator does with characters outside of ASCII, such as accented or CJK file names.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum NonAsciiPolicy {
+    /// Leave non-ASCII characters in cleartext, which works whatever code page the script runs under.
+    #[default]
+    Passthrough,
+    /// Give every non-ASCII character its own alphabet entry, and switch the console to *code_page* with `chcp` before defining them.<br>
+    /// The output must then be saved in that code page, such as **65001** for UTF-8 or **437** for US OEM (see *Encoding::from_code_page*).
+    Encode { code_page: u32 },
+}
+
+
+/// ### A builder which configures and produces an immutable BatchObfuscator.<br>
+/// ### Example Usage:<br>
+/// ```
+/// use oxidizer::batch::{
+///     CharSet,
+///     obfuscator::ObfuscatorConfig,
+/// };
+/// 
+/// # fn main() -> oxidizer::Result<()> {
+/// let obfuscator = ObfuscatorConfig::new()
+///     .name_length(4, 12)
+///     .echo_off(true)
+///     .watermark(false)
+///     .charset(CharSet::Letters)
+///     .build()?;
+/// 
+/// let first = obfuscator.obfuscate_str("echo one")?;
+/// let second = obfuscator.obfuscate_str("echo two")?;
+/// assert!(first.code.starts_with("@echo off"));
+/// assert!(second.code.starts_with("@echo off"));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ObfuscatorConfig {
+    min_len: u32,
+    max_len: u32,
+    echo_off: bool,
+    watermark: bool,
+    charset: CharSet,
+    policy: DiagnosticPolicy,
+    seed: Option<u64>,
+    max_line_length: usize,
+    non_ascii: NonAsciiPolicy,
+}
+
+impl Default for ObfuscatorConfig {
+    fn default() -> Self {
+        ObfuscatorConfig {
+            min_len: 7,
+            max_len: 109,
+            echo_off: false,
+            watermark: true,
+            charset: CharSet::GoodChars,
+            policy: DiagnosticPolicy::default(),
+            seed: None,
+            max_line_length: MAX_LINE_LENGTH,
+            non_ascii: NonAsciiPolicy::default(),
+        }
+    }
+}
+
+impl ObfuscatorConfig {
+
+    /// Creates a new configuration with default values.
+    pub fn new() -> Self {
+        ObfuscatorConfig::default()
+    }
+
+    /// Sets length constraints on the obfuscated variable names.<br><br>
+    /// Min default value is (**7**), Max default value is (**109**).<br>
+    /// Names are always at least one character long, and *max* is raised to *min* if it is smaller.<br>
+    /// *min* is also raised until the range holds a unique name for every entry of the alphabet, since cmd ignores case in names.<br><br>
+    /// Batch has a single-line limit of **8191**, so keep this in mind when changing these values.<br><br>
+    /// Shorter commands can use larger values to generate more noise.<br>
+    /// Longer commands run the risk of breaking in the terminal if the obfuscated length exceeds the limit.
+    pub fn name_length(mut self, min: u32, max: u32) -> Self {
+        self.min_len = min.max(1);
+        self.max_len = max.max(self.min_len);
+        self.fit_name_length()
+    }
+
+    /// Writes boiler-plate "@echo off" to the output script to avoid echoing cleartext commands.
+    pub fn echo_off(mut self, echo_off: bool) -> Self {
+        self.echo_off = echo_off;
+        self
+    }
+
+    /// Controls whether the base64-encoded watermarks are written to the output script. Enabled by default.
+    pub fn watermark(mut self, watermark: bool) -> Self {
+        self.watermark = watermark;
+        self
+    }
+
+    /// Sets the character set used for the body of obfuscated variable names.<br><br>
+    /// Defaults to **CharSet::GoodChars**. Names always begin and end with a letter regardless.<br>
+    /// Only **CharSet::GoodChars**, **CharSet::DelayedSafe** and **CharSet::Letters** are safe in names, so any other set is narrowed down to the characters it shares with them.
+    pub fn charset(mut self, charset: CharSet) -> Self {
+        self.charset = match charset {
+            CharSet::FullSet | CharSet::GoodChars => CharSet::GoodChars,
+            CharSet::DelayedSafe => CharSet::DelayedSafe,
+            CharSet::Letters | CharSet::BadChars => CharSet::Letters,
+        };
+        self.fit_name_length()
+    }
+
+    /// Sets how the obfuscator handles user-defined variables, labels and set statements in input scripts.<br><br>
+    /// Defaults to **DiagnosticPolicy::Passthrough**, which never blocks.
+    pub fn diagnostic_policy(mut self, policy: DiagnosticPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Seeds the random number generator so that obfuscation is reproducible.<br><br>
+    /// The same input and seed always give byte-identical output with a given version of this crate.<br>
+    /// For output which must stay stable across versions, supply your own generator to *obfuscate_with_rng* instead.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Sets the longest line the obfuscator may emit, defaulting to cmd.exe's limit of **8191** characters.<br><br>
+    /// Lines which would exceed it are re-encoded with the shortest possible variable names.<br>
+    /// If that is still too long, obfuscation fails with **Error::LineTooLong**.
+    pub fn max_line_length(mut self, max_line_length: usize) -> Self {
+        self.max_line_length = max_line_length;
+        self
+    }
+
+    /// Sets how characters outside of ASCII are handled.<br><br>
+    /// Defaults to **NonAsciiPolicy::Passthrough**, which leaves them in cleartext.
+    pub fn non_ascii(mut self, policy: NonAsciiPolicy) -> Self {
+        self.non_ascii = policy;
+        self
+    }
+
+    /// Raises the minimum name length until the name space can hold the alphabet along with the prelude's three names.<br>
+    /// Names are counted without `!`, which scripts enabling delayed expansion cannot use.
+    fn fit_name_length(mut self) -> Self {
+        let required: u64 = (CharSet::FullSet.values().len() - CharSet::BadChars.values().len() + 3) as u64;
+        while name_space(self.min_len, self.max_len, &self.charset.without_bang()) < required {
+            self.min_len += 1;
+            self.max_len = self.max_len.max(self.min_len);
+        };
+        self
+    }
+
+    /// Produces an immutable BatchObfuscator using this configuration.<br><br>
+    /// Returns **Error::UnsupportedCodePage** if **NonAsciiPolicy::Encode** names a code page the output cannot be written in (see *Encoding::from_code_page*).
+    pub fn build(self) -> Result<BatchObfuscator> {
+        if let NonAsciiPolicy::Encode { code_page } = self.non_ascii {
+            Encoding::from_code_page(code_page).ok_or(Error::UnsupportedCodePage(code_page))?;
+        };
+
+        Ok(BatchObfuscator { config: self })
+    }
+}
+
+
 /// ### An object that generates obfuscated batch commands from un-obfuscated source commands.<br>
+/// The obfuscator holds only its configuration, so it can be reused for any number of inputs.<br>
 /// ### Example Usage:<br>
 /// ```
 /// use oxidizer::batch::obfuscator::BatchObfuscator;
 /// 
+/// # fn main() -> oxidizer::Result<()> {
 /// let poc = "start C:/Windows/System32/calc.exe";
-/// let mut obfuscator = BatchObfuscator::new();
-/// obfuscator.initialize(None, None, poc.to_string());
+/// let obfuscator = BatchObfuscator::new();
+/// let obfuscated = obfuscator.obfuscate_str(poc)?;
 /// 
-/// let obfuscated_script = obfuscator.write_obfuscated_script(None);
-/// 
-/// // prints: "Obfuscated code was written to: obfuscated.bat".
-/// println!("Obfuscated code was written to: {}", obfuscated_script);
-#[derive(Debug, Default)]
+/// // The obfuscated script never touches the filesystem unless you write it somewhere yourself.
+/// let mut buffer: Vec<u8> = Vec::new();
+/// obfuscated.write_obfuscated_script(&mut buffer)?;
+/// assert_eq!(buffer, obfuscated.code.as_bytes());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
 pub struct BatchObfuscator {
+    config: ObfuscatorConfig,
+}
+
+/// The in-memory result of obfuscating a script with a BatchObfuscator.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Obfuscated {
+    pub code: String,
     pub set_str: String,
     pub space_str: String,
     pub eq_str: String,
-    pub used_variable_strings: HashSet<String>,
     pub alphabet: HashMap<char, String>,
-    pub prep_commands: Vec<String>,
-    pub exec_commands: Vec<String>,
-    pub obfuscated_code: String,
-    echo_mode: bool,
-    warn_mode: bool,
-    initialized: bool,
+    pub diagnostics: Vec<Diagnostic>,
 }
 
 impl BatchObfuscator {
 
-    /// Creates a new, empty instance of a BatchObfuscator.
+    /// Creates a new BatchObfuscator with the default configuration.
     pub fn new() -> Self {
-        BatchObfuscator { 
+        BatchObfuscator { config: ObfuscatorConfig::default() }
+    }
+
+    /// Returns the configuration this obfuscator was built with.
+    pub fn config(&self) -> &ObfuscatorConfig {
+        &self.config
+    }
+
+    /// Builds a fresh obfuscated alphabet, and uses it to obfuscate the provided source code entirely in memory.<br><br>
+    /// Uses the configured seed if there is one, otherwise a randomly-seeded generator.<br>
+    /// Returns the obfuscated script along with the alphabet and diagnostics that produced it.<br>
+    /// Constructs which cannot be obfuscated are collected into *diagnostics*.<br>
+    /// Returns **Error::Aborted** if the diagnostic policy rejects them.
+    pub fn obfuscate_str(&self, src: &str) -> Result<Obfuscated> {
+        let mut rng: StdRng = match self.config.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+
+        self.obfuscate_with_rng(src, &mut rng)
+    }
+
+    /// Same as *obfuscate_str*, but draws all randomness from the supplied generator and ignores the configured seed.
+    pub fn obfuscate_with_rng<R: RngCore + ?Sized>(&self, src: &str, rng: &mut R) -> Result<Obfuscated> {
+        let mut session: Session<R> = Session::new(&self.config, rng);
+
+        // Variables referenced by the script keep their names, so generated names must steer clear of them.
+        session.reserve_names(src);
+        session.delayed_expansion = enables_delayed_expansion(src);
+        session.prelude()?;
+
+        // Build an obfuscated alphabet with variables and push their assignment statements into the prep_commands Vec.
+        session.build_alphabet(src)?;
+
+        // Obfuscate the cleartext source code using our newly-created obfuscated alphabet.
+        let code: String = session.obfuscate(src)?;
+
+        Ok(Obfuscated {
+            code,
+            set_str: session.set_str,
+            space_str: session.space_str,
+            eq_str: session.eq_str,
+            alphabet: session.alphabet,
+            diagnostics: session.diagnostics,
+        })
+    }
+}
+
+impl Obfuscated {
+
+    /// Writes the obfuscated script to any writer, such as a file, socket, buffer or stdout.<br><br>
+    /// Returns **Error::Io** if writing fails.
+    pub fn write_obfuscated_script<W: Write>(&self, mut writer: W) -> Result<()> {
+        writer.write_all(self.code.as_bytes())?;
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+
+/// The working state of a single obfuscation run, discarded once the output has been produced.
+struct Session<'a, R: RngCore + ?Sized> {
+    config: &'a ObfuscatorConfig,
+    rng: &'a mut R,
+    set_str: String,
+    space_str: String,
+    eq_str: String,
+    used_variable_strings: HashSet<String>,
+    delayed_expansion: bool,
+    alphabet: HashMap<char, String>,
+    compact_alphabet: HashMap<char, String>,
+    prep_commands: Vec<String>,
+    exec_commands: Vec<String>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a, R: RngCore + ?Sized> Session<'a, R> {
+
+    fn new(config: &'a ObfuscatorConfig, rng: &'a mut R) -> Self {
+        Session {
+            config,
+            rng,
             set_str: String::new(),
             space_str: String::new(),
             eq_str: String::new(),
             used_variable_strings: HashSet::new(),
+            delayed_expansion: false,
             alphabet: HashMap::new(),
+            compact_alphabet: HashMap::new(),
             prep_commands: Vec::new(),
             exec_commands: Vec::new(),
-            obfuscated_code: String::new(),
-            echo_mode: false,
-            warn_mode: true,
-            initialized: false,
+            diagnostics: Vec::new(),
         }
     }
 
-    /// Prevents from writing boiler-plate "@echo off" to output script.
-    pub fn dont_echo(&mut self) {
-        self.echo_mode = true;
-    }
+    /// Generates a variable name which has not yet been used in this session.<br><br>
+    /// Names are drawn without `!` if the script enables delayed expansion, which would otherwise mangle them.<br>
+    /// Returns **Error::NamesExhausted** if the configured name lengths have no unused names left.
+    fn fresh_name(&mut self) -> Result<String> {
+        let charset: CharSet = match self.delayed_expansion {
+            true => self.config.charset.without_bang(),
+            false => self.config.charset,
+        };
+        let name: String = generate_random_chars(
+            Some(self.config.min_len),
+            Some(self.config.max_len),
+            &charset,
+            &self.used_variable_strings,
+            self.rng,
+        )?;
+        self.used_variable_strings.insert(name.to_lowercase());
 
-    /// Disables warnings about user-defined variables in input scripts.
-    pub fn dont_warn(&mut self) {
-        self.warn_mode = false;
+        Ok(name)
     }
 
-    /// Initializes an empty BatchObfuscator, builds an obfuscated alphabet, and uses it to obfuscate the provided source code.<br><br>
-    /// *Min/Max* refer to length constraints on the obfuscated variable names.<br>
-    /// Call with *min* or *max* set to *None* to use default values.<br>
-    /// Min default value is (**7**), Max default value is (**109**).<br><br>
-    /// Batch has a single-line limit of **8191**, so keep this in mind when changing these values.<br><br>
-    /// Shorter commands can use larger values to generate more noise.<br>
-    /// Longer commands run the risk of breaking in the terminal if the obfuscated length exceeds the limit.
-    pub fn initialize(&mut self, min: Option<u32>, max: Option<u32>, src: String) {
+    /// Generates the shortest variable name which has not yet been used in this session, for lines close to the length limit.
+    fn compact_name(&mut self) -> Result<String> {
+        let name: String = generate_random_chars(Some(1), Some(2), &CharSet::Letters, &self.used_variable_strings, self.rng)?;
+        self.used_variable_strings.insert(name.to_lowercase());
 
-        // Create obfuscated variables for the set keyword, the space character, and the assignment operator.
-        self.set_str = generate_random_chars(min, max, &self.used_variable_strings);
-        self.space_str = generate_random_chars(min, max, &self.used_variable_strings);
-        self.eq_str = generate_random_chars(min, max, &self.used_variable_strings);
-
-        // Insert base64-encoded watermarks to assist any potential deobfuscation attempts in the wild.
-        // Write the script header defining an obfuscated way of assigning further variables.
-        self.prep_commands.push(String::from(":: VGhpcyBmaWxlIHdhcyBvYmZ1c2NhdGVkIHZpYSBodHRwczovL2dpdGh1Yi5jb20vMHhUYXMvMHhpZGl6M3I="));
-        self.prep_commands.push(String::from(":: VGhpcyBmaWxlIGNhbiBiZSBwcm9ncmFtYXRpY2FsbHkgZGVvYmZ1c2NhdGVkIHZpYSBodHRwczovL2dpdGh1Yi5jb20vMHhUYXMvMHhpZGl6M3I="));
-        if self.echo_mode {self.prep_commands.push(String::from("@echo off"));};
-        self.prep_commands.push(format!("set {}=set", self.set_str));
-        self.prep_commands.push(format!("%{}% {}= ", self.set_str, self.space_str));
-        self.prep_commands.push(format!("%{}%%{}%{}==", self.set_str, self.space_str, self.eq_str));
+        Ok(name)
+    }
 
-        // Build an obfuscated alphabet with variables and push their assignment statements into the prep_commands Vec.
-        self.build_alphabet(min, max);
+    /// Marks the name of every variable referenced in the source as used, since cmd treats names case-insensitively.
+    fn reserve_names(&mut self, src: &str) {
+        let mut scopes: ExpansionScopes = ExpansionScopes::new();
 
-        // Obfuscate the cleartext source code using our newly-created obfuscated alphabet.
-        self.obfuscate(src);
+        for line in src.split('\n') {
+            let tokens: Vec<Token> = tokenize(line, scopes.delayed_expansion());
+            for token in &tokens {
+                if let Some(name) = token.variable_name() {
+                    self.used_variable_strings.insert(name.to_lowercase());
+                };
+            };
 
-        self.initialized = true;
+            if let Some(change) = scope_change(&tokens) {
+                scopes.apply(change);
+            };
+        };
     }
 
-    /// Writes the obfuscated source of a pre-initialized BatchObfuscator to a file, and returns a string containing the name of that file.<br><br>
-    /// Output filename defaults to *obfuscated.bat* when **None** is passed into the parameter.<br><br>
-    /// **This method panics if file creation/writing fails.**
-    pub fn write_obfuscated_script(&self, file_name: Option<String>) -> String {
-
-        if !self.initialized {
-            eprintln!("\nObfuscator must first be initialized!");
-            exit(1);
-        };
+    /// Writes the script header defining an obfuscated way of assigning further variables.
+    fn prelude(&mut self) -> Result<()> {
 
-        let handle_name: String = file_name.unwrap_or_else(|| String::from("obfuscated.bat"));
-        let handle_clone: String = handle_name.clone();
+        // Create obfuscated variables for the set keyword, the space character, and the assignment operator.
+        self.set_str = self.fresh_name()?;
+        self.space_str = self.fresh_name()?;
+        self.eq_str = self.fresh_name()?;
 
-        let mut file = File::create(handle_clone.as_str()).expect("Failed to create file!");
-        file.write_all(self.obfuscated_code.as_bytes()).expect("Failed writing to file!");
+        if self.config.watermark {
+            self.prep_commands.extend(WATERMARKS.iter().map(|mark| mark.to_string()));
+        };
+        if self.config.echo_off {self.prep_commands.push(String::from("@echo off"));};
+        if let NonAsciiPolicy::Encode { code_page } = self.config.non_ascii {
+            self.prep_commands.push(format!("chcp {}>nul", code_page));
+        };
+        self.prep_commands.push(format!("set {}=set", self.set_str));
+        self.prep_commands.push(format!("%{}% {}= ", self.set_str, self.space_str));
+        self.prep_commands.push(format!("%{}%%{}%{}==", self.set_str, self.space_str, self.eq_str));
 
-        handle_name
+        Ok(())
     }
 
-
-    /* Utility */
-
-    /// Builds an obfuscated alphabet using the Batch obfuscation character set.
-    fn build_alphabet(&mut self, min: Option<u32>, max: Option<u32>) {
+    /// Builds an obfuscated alphabet using the Batch obfuscation character set.<br><br>
+    /// Under **NonAsciiPolicy::Encode**, every non-ASCII character of the source is added as well, in order of first appearance.
+    fn build_alphabet(&mut self, src: &str) -> Result<()> {
 
         for chr in CharSet::FullSet.values() {
         
             if !CharSet::BadChars.values().contains(&chr) {
-                let varname: String = generate_random_chars(min, max, &self.used_variable_strings);
-                self.alphabet.insert(chr, varname.clone());
-
-                if !self.prep_commands.contains(&BatchObfuscator::define_batch_variable(
-                                            varname.to_owned().to_string(),
-                                            format!("{}", chr.to_owned()),
-                                            self)) 
-                {
-                    self.prep_commands.push(BatchObfuscator::define_batch_variable(
-                                            varname.to_owned().to_string(),
-                                            format!("{}", chr.to_owned()),
-                                            self));
+                let varname: String = self.fresh_name()?;
+                let varline: String = self.define_batch_variable(&varname, &chr.to_string());
+                self.alphabet.insert(chr, varname);
+
+                if !self.prep_commands.contains(&varline) {
+                    self.prep_commands.push(varline);
                 };
             }else {
                 self.alphabet.insert(chr, format!("{}", chr));
             };
         };
-    }
 
-    /// Obfuscates cleartext batch commands using an obfuscated alphabet of variables.
-    fn obfuscate(&mut self, src: String) {
-
-        let match_variable_lines: Regex = Regex::new("%[a-zA-Z0-9_-~!@#$^&/.,<>;:'\"=]+%").expect("Regex not valid!");
-        let match_set_lines: Regex = Regex::new("set .+=.+").expect("Regex not valid!");
-        let src_list: Vec<&str> = src.split('\n').collect();
-        let mut warned: bool = false;
+        if let NonAsciiPolicy::Encode { .. } = self.config.non_ascii {
+            for chr in src.chars().filter(|chr| !chr.is_ascii()) {
+                if !self.alphabet.contains_key(&chr) {
+                    let varname: String = self.fresh_name()?;
+                    self.prep_commands.push(self.define_batch_variable(&varname, &chr.to_string()));
+                    self.alphabet.insert(chr, varname);
+                };
+            };
+        };
 
-        for line in src_list {
+        Ok(())
+    }
 
-            // Find char indicies for any occurances of '%' in a line.
-            let find_percent_index = || {
-                let mut perc_index: Vec<usize> = Vec::new();
-                for (i, c) in line.char_indices() {
-                    if c == '%' {
-                        perc_index.push(i);
-                    };
-                };
+    /// Obfuscates cleartext batch commands using an obfuscated alphabet of variables, and returns the finished script.
+    fn obfuscate(&mut self, src: &str) -> Result<String> {
+        let match_set_names: Regex = Regex::new(r#"(?i)^\s*@?set\s+(?:/[ap]\s+)?"?([^=\s"]+)="#).expect("Regex not valid!");
+        let src_list: Vec<&str> = src.split('\n').collect();
 
-                if perc_index.is_empty() { return None };
+        // Variables assigned anywhere in the script are user-defined, anything else is assumed to come from the environment.
+        let defined_names: HashSet<String> = src_list.iter()
+            .filter_map(|line| match_set_names.captures(line))
+            .map(|caps| caps[1].to_lowercase())
+            .collect();
 
-                Some(perc_index)
+        let mut scopes: ExpansionScopes = ExpansionScopes::new();
+        let mut blocks: Blocks = Blocks::new();
+        let mut block_assigned: HashSet<String> = HashSet::new();
+        for (line_num, line) in src_list.iter().copied().enumerate() {
+            let tokens: Vec<Token> = tokenize(line, scopes.delayed_expansion());
+            if let Some(change) = scope_change(&tokens) {
+                scopes.apply(change);
             };
 
-            if line.contains('%') && !match_variable_lines.is_match(line) {
+            let is_label_line: bool = tokens.iter().any(|token| token.kind == TokenKind::Label);
+            let is_set_line: bool = is_set_command(&tokens);
 
-                let perc_index: Vec<usize> = find_percent_index().expect("No percent symbols in sample!");
+            // Labels and set lines are passed through, while everything else is encoded around any variable references.
+            let passthrough: bool = is_label_line || is_set_line;
 
-                let mut skip: bool = false;
-                for (i, c) in line.char_indices() {
-
-                    if skip {
-                        skip = false;
-                        continue;
-                    };
+            // Variable references must stay literal to keep working, so record diagnostics about this method's limitations.
+            for token in tokens.iter().filter(|token| token.kind == TokenKind::Variable) {
+                let name: String = token.variable_name().unwrap_or_default().to_lowercase();
+                let kind: DiagnosticKind = if defined_names.contains(&name) {
+                    DiagnosticKind::UserVariable
+                }else {
+                    DiagnosticKind::EnvironmentVariable
+                };
 
-                    if perc_index.contains(&i) {
-                        let blob: &str = &line[i..=i+1];
-                        let mut obfuscate_blob = || {
-                            let varname: String = generate_random_chars(None, None, &self.used_variable_strings);
-                            let varline: String = BatchObfuscator::define_batch_variable(varname.clone(), blob.to_string(), self);
-
-                            self.prep_commands.push(varline);
-                            self.exec_commands.push(format!("%{}%", varname));
-                        };
-                        obfuscate_blob();
-                        skip = true;
-                        continue;
-                    };
+                self.diagnostics.push(Diagnostic { line: line_num + 1, span: token.span.clone(), kind });
+            };
 
-                    if !CharSet::FullSet.values().contains(&c) {
-                        self.exec_commands.push(format!("{}", c.to_owned()));
-                    }else if !CharSet::BadChars.values().contains(&c) {
-                        let varname: &String = self.alphabet.get(&c).expect("Key not in alphabet!");
-                        self.exec_commands.push(format!("%{}%", varname.to_owned()));
-                    }else {
-                        self.exec_commands.push(format!("{}", c.to_owned()));
+            // A block or chained line is expanded as a whole, so a reference cannot see a `set` made earlier within it.
+            // The alphabet's definitions all go in the prelude, so only the input's own assignments can be caught out like this.
+            for command in blocks.commands(&tokens) {
+                for token in tokens.iter().filter(|token| token.kind == TokenKind::Variable && command.contains(&token.span.start)) {
+                    if block_assigned.contains(&token.variable_name().unwrap_or_default().to_lowercase()) {
+                        self.diagnostics.push(Diagnostic { line: line_num + 1, span: token.span.clone(), kind: DiagnosticKind::SameBlockReference });
                     };
                 };
+                if let Some(caps) = match_set_names.captures(&line[command]) {
+                    block_assigned.insert(caps[1].to_lowercase());
+                };
+            };
+            if !blocks.is_open(&tokens) {
+                block_assigned.clear();
+            };
 
-            // If the input script contains custom/environment vars, warn about this method's limitations.
-            }else if match_variable_lines.is_match(line) ||
-                (line.starts_with(':') && !line.starts_with("::")) || 
-                (match_set_lines.is_match(line) && line.to_lowercase().starts_with("set")) {
-
-                let mut heed: String = String::new();
-                if !warned && self.warn_mode {
-                    println!("\n[!]--> WARNING: Because of the way this obfuscation method works, 
-                        variables you define or use in your scripts, including environment variables,
-                        and function labels, cannot be effectively obfuscated using this obfuscation method, 
-                        and lines containing them will be printed as-is in order to preserve functionality.");
+            let encoded: String = if !passthrough {
 
-                    heed = input("\nContinue Anyway? [Y/N] ~> ");
+                // Reassemble input source using obfuscated alphabet variables, shrinking them if the line grows too long.
+                let (mut encoded, mut definitions) = self.encode_line(&tokens, false)?;
+                if encoded.chars().count() > self.config.max_line_length {
+                    (encoded, definitions) = self.encode_line(&tokens, true)?;
                 };
 
-                if !self.warn_mode || heed.to_lowercase().contains('y') || warned {
-                    self.exec_commands.push(line.to_owned());
-                    warned = true;
-                }else {
-                    println!("\nObfuscation aborted!");
-                    exit(0);
-                };
+                self.prep_commands.extend(definitions);
+                encoded
             }else {
 
-                // Reassemble input source using obfuscated alphabet variables.
-                for chr in line.chars() {
+                if is_label_line {
+                    self.diagnostics.push(Diagnostic { line: line_num + 1, span: 0..line.len(), kind: DiagnosticKind::Label });
+                };
+                if is_set_line {
+                    self.diagnostics.push(Diagnostic { line: line_num + 1, span: 0..line.len(), kind: DiagnosticKind::SetLine });
+                };
 
-                    if !CharSet::FullSet.values().contains(&chr) {
-                        self.exec_commands.push(format!("{}", chr.to_owned()));
-                    }else if !CharSet::BadChars.values().contains(&chr) {
-                        let varname: &String = self.alphabet.get(&chr).expect("Key not in alphabet!");
-                        self.exec_commands.push(format!("%{}%", varname.to_owned()));
-                    }else {
-                        self.exec_commands.push(format!("{}", chr.to_owned()));
-                    };
+                line.to_owned()
+            };
+
+            // cmd.exe refuses lines beyond its length limit, so there is no point in emitting one.
+            let projected: usize = encoded.chars().count();
+            if projected > self.config.max_line_length {
+                let diagnostic: Diagnostic = Diagnostic {
+                    line: line_num + 1,
+                    span: 0..line.len(),
+                    kind: DiagnosticKind::LineTooLong { projected },
                 };
+                self.diagnostics.push(diagnostic.clone());
+
+                return Err(Error::LineTooLong(diagnostic));
             };
 
+            self.exec_commands.push(encoded);
             self.exec_commands.push("\n".to_string());
         };
 
+        // Diagnosed constructs have already been kept as they are, so only a rejection needs handling here.
+        if !self.diagnostics.is_empty() {
+            let proceed: bool = match &self.config.policy {
+                DiagnosticPolicy::Abort => false,
+                DiagnosticPolicy::Passthrough => true,
+                DiagnosticPolicy::Ask(callback) => callback(&self.diagnostics),
+            };
+
+            if !proceed {
+                return Err(Error::Aborted);
+            };
+        };
+
         // Convert obfuscated output to a string and append it to the prep_commands Vec.
         let exec_string: String = self.exec_commands.join("");
         self.prep_commands.push(exec_string);
-        self.prep_commands.push(String::from(":: VGhpcyBmaWxlIHdhcyBvYmZ1c2NhdGVkIHZpYSBodHRwczovL2dpdGh1Yi5jb20vMHhUYXMvMHhpZGl6M3I="));
-        self.prep_commands.push(String::from(":: VGhpcyBmaWxlIGNhbiBiZSBwcm9ncmFtYXRpY2FsbHkgZGVvYmZ1c2NhdGVkIHZpYSBodHRwczovL2dpdGh1Yi5jb20vMHhUYXMvMHhpZGl6M3I="));
+        if self.config.watermark {
+            self.prep_commands.extend(WATERMARKS.iter().map(|mark| mark.to_string()));
+        };
 
-        // Join the obfuscated output on newlines and complete the initialization.
-        self.obfuscated_code = self.prep_commands.join("\n");
+        // Join the obfuscated output on newlines to complete the script.
+        Ok(self.prep_commands.join("\n"))
+    }
+
+    /// Encodes a single tokenized line with the obfuscated alphabet, returning the encoded line and any new definitions it depends on.<br><br>
+    /// Variable, argument and FOR variable references such as `%1`, `%~dp0` or `%%i` are kept literal, while `%%` and lone percents are stored verbatim in a variable of their own.<br>
+    /// In *compact* mode, characters are encoded with the shortest available variable names instead.
+    fn encode_line(&mut self, tokens: &[Token], compact: bool) -> Result<(String, Vec<String>)> {
+        let mut encoded: String = String::new();
+        let mut definitions: Vec<String> = Vec::new();
+
+        for token in tokens {
+
+            // References are left as they are, so that cmd still expands them at runtime, where a subroutine sees its own arguments.
+            if matches!(token.kind, TokenKind::Variable | TokenKind::Argument | TokenKind::ForVariable) {
+                encoded.push_str(token.text);
+                continue;
+            };
+
+            if token.is_percent_construct() {
+                let varname: String = if compact { self.compact_name()? } else { self.fresh_name()? };
+
+                definitions.push(self.define_batch_variable(&varname, token.text));
+                encoded.push_str(&format!("%{}%", varname));
+                continue;
+            };
+
+            for c in token.text.chars() {
+                let encodable: bool = CharSet::FullSet.values().contains(&c) || (!c.is_ascii() && self.alphabet.contains_key(&c));
+
+                if !encodable || CharSet::BadChars.values().contains(&c) {
+                    encoded.push(c);
+                }else if compact {
+                    let varname: String = match self.compact_alphabet.get(&c) {
+                        Some(varname) => varname.to_owned(),
+                        None => {
+                            let varname: String = self.compact_name()?;
+                            definitions.push(self.define_batch_variable(&varname, &c.to_string()));
+                            self.compact_alphabet.insert(c, varname.clone());
+                            varname
+                        },
+                    };
+                    encoded.push_str(&format!("%{}%", varname));
+                }else {
+                    let varname: &String = self.alphabet.get(&c).ok_or(Error::UnknownAlphabetKey(c))?;
+                    encoded.push_str(&format!("%{}%", varname));
+                };
+            };
+        };
+
+        Ok((encoded, definitions))
     }
 
     /// Returns a string representing an obfuscated variable definition statement in Batch.
-    fn define_batch_variable(name: String, value: String, prelude: &BatchObfuscator)
-    -> String {
-        format!("%{}%%{}%{}%{}%{}", prelude.set_str,
-                prelude.space_str, name, prelude.eq_str, value)
+    fn define_batch_variable(&self, name: &str, value: &str) -> String {
+        format!("%{}%%{}%{}%{}%{}", self.set_str,
+                self.space_str, name, self.eq_str, value)
     }
 
 }
 
 
+/// Returns *true* if any line of the source switches delayed expansion on with `setlocal EnableDelayedExpansion`.
+fn enables_delayed_expansion(src: &str) -> bool {
+    src.split('\n').any(|line| {
+        scope_change(&tokenize(line, false)) == Some(ScopeChange::Setlocal { delayed_expansion: Some(true) })
+    })
+}
+
+/// Returns *true* if a tokenized line is a `set` command which assigns a value.
+fn is_set_command(tokens: &[Token]) -> bool {
+    let mut words = tokens.iter().filter(|token| token.kind != TokenKind::Whitespace);
+
+    match words.next() {
+        Some(first) if first.kind == TokenKind::Text && first.text.trim_start_matches('@').eq_ignore_ascii_case("set") => {
+            words.any(|token| token.text.contains('='))
+        },
+        _ => false,
+    }
+}
+
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::batch::{
+        deobfuscator::BatchDeobfuscator,
+        emulator::{
+            Emulated,
+            Emulator,
+        },
+    };
+
+    /// Obfuscates *src* with *config*, asserting that deobfuscating the result gives *src* back.
+    fn roundtrip(src: &str, config: ObfuscatorConfig) -> Obfuscated {
+        let obfuscated: Obfuscated = config.build().unwrap().obfuscate_str(src).unwrap();
+        let restored: String = BatchDeobfuscator::new().deobfuscate_str(&obfuscated.code).unwrap().code;
+
+        assert_eq!(restored, src);
+        obfuscated
+    }
 
     #[test]
     fn test_charset() {
-        println!("Full_Charset: {:#?}\n Letters: {:#?}\n, BadChars: {:#?}",
-                CharSet::FullSet.values(), CharSet::Letters.values(),
-                CharSet::BadChars.values());
+        let full: Vec<char> = CharSet::FullSet.values();
+        let good: Vec<char> = CharSet::GoodChars.values();
+        let bad: Vec<char> = CharSet::BadChars.values();
+
+        assert_eq!(full.iter().collect::<HashSet<&char>>().len(), full.len());
+        assert!(good.iter().chain(&bad).all(|c| full.contains(c)));
+        assert!(good.iter().all(|c| !bad.contains(c)));
+        assert!(CharSet::Letters.values().iter().all(|c| good.contains(c)));
+
+        // Names start and end with a letter, and draw the rest from the charset they were given.
+        let mut rng: StdRng = StdRng::seed_from_u64(1);
+        for charset in [CharSet::Letters, CharSet::GoodChars] {
+            for _ in 0..32 {
+                let name: Vec<char> = generate_random_chars(Some(8), Some(16), &charset, &HashSet::new(), &mut rng).unwrap().chars().collect();
+
+                assert!((8..=16).contains(&name.len()));
+                assert!(name[0].is_ascii_alphabetic() && name[name.len() - 1].is_ascii_alphabetic());
+                assert!(name.iter().all(|c| charset.values().contains(c)));
+            };
+        };
     }
 
     #[test]
     fn test_prelude() {
-        let obfuscator: BatchObfuscator = BatchObfuscator::new();
+        let obfuscator: BatchObfuscator = ObfuscatorConfig::new().echo_off(true).watermark(false).seed(3).build().unwrap();
+        let obfuscated: Obfuscated = obfuscator.obfuscate_str("echo hi").unwrap();
+        let (set, space, eq): (&str, &str, &str) = (&obfuscated.set_str, &obfuscated.space_str, &obfuscated.eq_str);
+        let lines: Vec<&str> = obfuscated.code.lines().collect();
+
+        assert_eq!(lines[0], "@echo off");
+        assert_eq!(lines[1], format!("set {}=set", set));
+        assert_eq!(lines[2], format!("%{}% {}= ", set, space));
+        assert_eq!(lines[3], format!("%{}%%{}%{}==", set, space, eq));
+
+        // Every other alphabet entry is then defined through those three names, ahead of the encoded command.
+        let bad: Vec<char> = CharSet::BadChars.values();
+        let definitions: &[&str] = &lines[4..lines.len() - 1];
+        assert_eq!(definitions.len(), obfuscated.alphabet.keys().filter(|chr| !bad.contains(chr)).count());
+        for (chr, name) in obfuscated.alphabet.iter().filter(|(chr, _)| !bad.contains(chr)) {
+            assert!(definitions.contains(&format!("%{}%%{}%{}%{}%{}", set, space, name, eq, chr).as_str()));
+        };
+    }
+
+    #[test]
+    fn test_in_memory_roundtrip() {
+        roundtrip("start C:/Windows/System32/calc.exe", ObfuscatorConfig::new());
+    }
+
+    #[test]
+    fn test_reuse_without_leftover_state() {
+        let obfuscator: BatchObfuscator = ObfuscatorConfig::new()
+            .name_length(3, 5)
+            .watermark(false)
+            .build().unwrap();
+
+        let first: Obfuscated = obfuscator.obfuscate_str("echo %d one").unwrap();
+        let second: Obfuscated = obfuscator.obfuscate_str("echo one").unwrap();
+
+        // Only the second run's own definitions should appear: a set/space/equals trio plus one per alphabet entry.
+        let defined: usize = CharSet::FullSet.values().iter()
+            .filter(|chr| !CharSet::BadChars.values().contains(chr))
+            .count();
+        assert!(first.code.lines().count() > second.code.lines().count());
+        assert_eq!(second.code.lines().count(), 3 + defined + 1);
+        assert!(!second.code.contains(WATERMARKS[0]));
+
+        for name in second.alphabet.values().filter(|name| name.len() > 1) {
+            assert!((3..=5).contains(&name.len()));
+        };
+    }
+
+    #[test]
+    fn test_seeded_output_is_deterministic() {
+        let src: &str = "start C:/Windows/System32/calc.exe\necho %d done";
+        let obfuscator: BatchObfuscator = ObfuscatorConfig::new().seed(0x7a5).build().unwrap();
+
+        let first: Obfuscated = obfuscator.obfuscate_str(src).unwrap();
+        let second: Obfuscated = obfuscator.obfuscate_str(src).unwrap();
+        assert_eq!(first.code, second.code);
+
+        let mut rng: StdRng = StdRng::seed_from_u64(0x7a5);
+        let injected: Obfuscated = BatchObfuscator::new().obfuscate_with_rng(src, &mut rng).unwrap();
+        assert_eq!(first.code, injected.code);
+
+        let reseeded: Obfuscated = ObfuscatorConfig::new().seed(0x7a6).build().unwrap().obfuscate_str(src).unwrap();
+        assert_ne!(first.code, reseeded.code);
+    }
+
+    #[test]
+    fn test_line_length_limit() {
+        let long_line: String = "echo The quick brown fox jumps over the lazy dog. ".repeat(30);
+        let src: String = format!("@echo off\n{}\npause", long_line);
+
+        // The long line is shrunk to fit, while the short lines keep their full-length names.
+        let obfuscated: Obfuscated = roundtrip(&src, ObfuscatorConfig::new().seed(8191));
+        assert!(obfuscated.code.lines().all(|line| line.chars().count() <= MAX_LINE_LENGTH));
+
+        // Once even the shortest names cannot fit, obfuscation fails and points at the offending line.
+        let src: String = format!("@echo off\n{}", long_line.repeat(3));
+        match ObfuscatorConfig::new().seed(8191).build().unwrap().obfuscate_str(&src) {
+            Err(Error::LineTooLong(diagnostic)) => {
+                assert_eq!(diagnostic.line, 2);
+                assert!(matches!(diagnostic.kind, DiagnosticKind::LineTooLong { projected } if projected > MAX_LINE_LENGTH));
+            },
+            other => panic!("expected Error::LineTooLong, got {:?}", other),
+        };
+
+        let src: &str = "echo short enough";
+        let obfuscator: BatchObfuscator = ObfuscatorConfig::new().max_line_length(16).build().unwrap();
+        assert!(matches!(obfuscator.obfuscate_str(src), Err(Error::LineTooLong(_))));
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let src: &str = "set name=World\n:greet\necho Hello %name% from %COMPUTERNAME%";
+
+        let obfuscated: Obfuscated = BatchObfuscator::new().obfuscate_str(src).unwrap();
+
+        let kinds: Vec<(usize, DiagnosticKind)> = obfuscated.diagnostics.iter().map(|d| (d.line, d.kind)).collect();
+        assert_eq!(kinds, vec![
+            (1, DiagnosticKind::SetLine),
+            (2, DiagnosticKind::Label),
+            (3, DiagnosticKind::UserVariable),
+            (3, DiagnosticKind::EnvironmentVariable),
+        ]);
+        assert_eq!(obfuscated.diagnostics[2].span, 11..17);
+
+        let obfuscator: BatchObfuscator = ObfuscatorConfig::new().diagnostic_policy(DiagnosticPolicy::Abort).build().unwrap();
+        assert!(matches!(obfuscator.obfuscate_str(src), Err(Error::Aborted)));
+    }
+
+    #[test]
+    fn test_diagnostic_callback_captures_state() {
+        use std::sync::{
+            Arc,
+            Mutex,
+        };
+
+        let seen: Arc<Mutex<Vec<usize>>> = Arc::new(Mutex::new(Vec::new()));
+        let allowed: usize = 3;
+        let log: Arc<Mutex<Vec<usize>>> = Arc::clone(&seen);
+        let obfuscator: BatchObfuscator = ObfuscatorConfig::new()
+            .diagnostic_policy(DiagnosticPolicy::Ask(Arc::new(move |diagnostics: &[Diagnostic]| {
+                log.lock().unwrap().push(diagnostics.len());
+                diagnostics.len() <= allowed
+            })))
+            .build().unwrap();
+
+        assert!(obfuscator.obfuscate_str("echo %a%").is_ok());
+        assert!(matches!(obfuscator.obfuscate_str("echo %a% %b% %c% %d%"), Err(Error::Aborted)));
+        assert_eq!(*seen.lock().unwrap(), vec![1, 4]);
+    }
+
+    #[test]
+    fn test_definitions_precede_every_block() {
+        let src: &str = "if exist out.txt (\n  del out.txt & echo 100%% é\n) else (echo %~dp0)\nfor %%f in (*.log) do (\n  type %%f\n)";
+
+        let obfuscated: Obfuscated = ObfuscatorConfig::new().watermark(false).seed(6).build().unwrap().obfuscate_str(src).unwrap();
+
+        let prefix: String = format!("%{}%%{}%", obfuscated.set_str, obfuscated.space_str);
+        let lines: Vec<&str> = obfuscated.code.lines().collect();
+        let last_definition: usize = lines.iter().rposition(|line| line.starts_with(&prefix)).unwrap();
+        assert_eq!(lines.len() - last_definition - 1, src.lines().count());
+        assert!(!obfuscated.diagnostics.iter().any(|d| d.kind == DiagnosticKind::SameBlockReference));
+    }
 
-        println!("{:#?}", obfuscator);
+    #[test]
+    fn test_same_block_references() {
+        let src: &str = "if 1==1 (\n  set name=World\n  echo %name%\n)\necho %name%\nset v=1& echo %v% & set /a n=%v%+1\necho !n!";
+
+        let obfuscated: Obfuscated = BatchObfuscator::new().obfuscate_str(src).unwrap();
+
+        let stale: Vec<(usize, std::ops::Range<usize>)> = obfuscated.diagnostics.iter()
+            .filter(|d| d.kind == DiagnosticKind::SameBlockReference)
+            .map(|d| (d.line, d.span.clone()))
+            .collect();
+        assert_eq!(stale, vec![(3, 7..13), (6, 14..17), (6, 29..32)]);
+    }
+
+    #[test]
+    fn test_percent_constructs_are_kept_whole() {
+        let src: &str = "copy %~dp0file.txt %1 & echo 100%% done 50%";
+
+        let obfuscated: Obfuscated = roundtrip(src, ObfuscatorConfig::new());
+
+        for blob in ["%%", "%"] {
+            assert!(obfuscated.code.lines().any(|line| line.ends_with(&format!("%{}%{}", obfuscated.eq_str, blob))));
+        };
+        let exec_line: &str = obfuscated.code.lines().find(|line| line.contains("%~dp0")).unwrap();
+        assert!(exec_line.contains("%1") && !exec_line.starts_with(&format!("%{}%", obfuscated.set_str)));
+    }
+
+    #[test]
+    fn test_variable_references_stay_literal() {
+        let src: &str = r"echo %USERPROFILE%\%~nx0 >> %log%";
+
+        let obfuscated: Obfuscated = roundtrip(src, ObfuscatorConfig::new());
+        let exec_line: &str = obfuscated.code.lines().find(|line| line.contains("%USERPROFILE%")).unwrap();
+
+        assert!(!exec_line.contains("echo"));
+        assert!(exec_line.contains("%log%") && exec_line.contains("%~nx0"));
+    }
+
+    #[test]
+    fn test_name_lengths_fit_the_alphabet() {
+        let obfuscated: Obfuscated = roundtrip("echo hi", ObfuscatorConfig::new().name_length(1, 1).seed(9));
+
+        let names: HashSet<String> = obfuscated.alphabet.values().map(|name| name.to_lowercase()).collect();
+        assert_eq!(names.len(), obfuscated.alphabet.len());
+
+        let letters: HashSet<String> = CharSet::Letters.values().iter().map(|c| c.to_lowercase().to_string()).collect();
+        let mut rng: StdRng = StdRng::seed_from_u64(0);
+        assert!(matches!(generate_random_chars(Some(1), Some(1), &CharSet::Letters, &letters, &mut rng), Err(Error::NamesExhausted)));
+    }
+
+    #[test]
+    fn test_unsafe_charsets_are_narrowed() {
+        for charset in [CharSet::FullSet, CharSet::BadChars] {
+            let obfuscated: Obfuscated = roundtrip("echo a=b & exit", ObfuscatorConfig::new().charset(charset).seed(2));
+
+            // Bad characters are mapped to themselves rather than to a name.
+            let safe: Vec<char> = CharSet::GoodChars.values();
+            assert!(obfuscated.alphabet.iter()
+                .filter(|(chr, _)| !CharSet::BadChars.values().contains(chr))
+                .all(|(_, name)| name.chars().all(|c| safe.contains(&c))));
+        };
+    }
+
+    #[test]
+    fn test_generated_names_avoid_script_variables() {
+        let src: &str = "echo %a%%B%%ab%";
+
+        for seed in 0..64 {
+            let obfuscator: BatchObfuscator = ObfuscatorConfig::new().name_length(1, 2).charset(CharSet::Letters).seed(seed).build().unwrap();
+            let obfuscated: Obfuscated = obfuscator.obfuscate_str(src).unwrap();
+
+            assert!(obfuscated.alphabet.values().all(|name| !["a", "b", "ab"].contains(&name.to_lowercase().as_str())));
+        };
+    }
+
+    #[test]
+    fn test_delayed_expansion_avoids_bang_in_names() {
+        let src: &str = "setlocal EnableDelayedExpansion\nset count=1\necho !count! & endlocal";
+
+        for seed in 0..16 {
+            let obfuscated: Obfuscated = roundtrip(src, ObfuscatorConfig::new().name_length(40, 60).seed(seed));
+
+            assert!(obfuscated.alphabet.values().all(|name| !name.contains('!')));
+            assert!(!obfuscated.set_str.contains('!') && !obfuscated.space_str.contains('!') && !obfuscated.eq_str.contains('!'));
+        };
+
+        // Names are drawn from a set without `!`, so the usual attempt limit and name space count still hold.
+        for charset in [CharSet::FullSet, CharSet::GoodChars, CharSet::DelayedSafe, CharSet::Letters] {
+            assert!(!charset.without_bang().values().contains(&'!'));
+        };
+        assert_eq!(name_space(3, 3, &CharSet::DelayedSafe) + 26 * 26, name_space(3, 3, &CharSet::GoodChars));
+    }
+
+    #[test]
+    fn test_for_variables_are_kept_whole() {
+        let src: &str = "for /r %%f in (*.log) do (\n  for %%g in (\"%%~dpf\") do echo %%~nxf in %%~g\n)\necho 100%";
+
+        let obfuscated: Obfuscated = roundtrip(src, ObfuscatorConfig::new());
+
+        assert!(obfuscated.code.lines().any(|line| line.ends_with(&format!("%{}%%", obfuscated.eq_str))));
+        for blob in ["%%f", "%%g", "%%~dpf", "%%~nxf", "%%~g"] {
+            assert!(!obfuscated.code.lines().any(|line| line.ends_with(&format!("%{}%{}", obfuscated.eq_str, blob))));
+            assert!(obfuscated.code.contains(blob));
+        };
+    }
+
+    #[test]
+    fn test_arguments_belong_to_their_subroutine() {
+        let src: &str = "@echo off\ncall :sub x\nexit /b\n:sub\necho %1 %~n1 %*";
+
+        let obfuscated: Obfuscated = ObfuscatorConfig::new().watermark(false).build().unwrap().obfuscate_str(src).unwrap();
+        let emulated: Emulated = Emulator::new().run(&obfuscated.code);
+
+        // Nothing in the prelude captures the script's own arguments, so the subroutine still reads the ones it was called with.
+        assert!(emulated.code.lines().any(|line| line == "echo %1 %~n1 %*"));
+        assert!(emulated.definitions.iter().all(|definition| !definition.value.contains('%')));
+        assert!(emulated.unresolved.is_empty());
+    }
+
+    #[test]
+    fn test_non_ascii_passthrough() {
+        let src: &str = "copy \"C:\\Users\\Zoë\\Café\\日本語.txt\" %~dp0é%%é";
+
+        let obfuscated: Obfuscated = roundtrip(src, ObfuscatorConfig::new());
+
+        assert!(!obfuscated.code.contains("chcp"));
+        assert!(obfuscated.code.contains("日本語"));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_non_ascii_encoded_for_code_page() {
+        // UTF-8 with CJK file names, and a US OEM script drawing a box.
+        let scripts: [(u32, &str); 2] = [
+            (65001, "@echo off\r\nmkdir \"%USERPROFILE%\\文档\\résumé\"\r\necho ✓ done\r\n"),
+            (437, "echo ╔══════╗\necho ║ Menü ║\necho ╚══════╝\necho Größe: 5 µm"),
+        ];
+
+        for (code_page, src) in scripts {
+            let obfuscated: Obfuscated = roundtrip(src, ObfuscatorConfig::new().non_ascii(NonAsciiPolicy::Encode { code_page }));
+
+            let chcp: String = format!("chcp {}>nul", code_page);
+            let prelude_start: usize = obfuscated.code.find(&format!("set {}=set", obfuscated.set_str)).unwrap();
+            assert!(obfuscated.code[..prelude_start].contains(&chcp));
+
+            // Non-ASCII characters only appear in definitions, never in the encoded commands.
+            let definition_prefix: String = format!("%{}%%{}%", obfuscated.set_str, obfuscated.space_str);
+            assert!(obfuscated.code.lines().filter(|line| !line.starts_with(&definition_prefix)).all(|line| line.is_ascii()));
+            assert!(Encoding::from_code_page(code_page).unwrap().encode(&obfuscated.code).is_ok());
+        };
+
+        // The output could not be saved in a code page without an encoder, so the configuration is refused outright.
+        let config: ObfuscatorConfig = ObfuscatorConfig::new().non_ascii(NonAsciiPolicy::Encode { code_page: 850 });
+        assert!(matches!(config.build(), Err(Error::UnsupportedCodePage(850))));
+    }
+
+    #[test]
+    fn test_non_ascii_survives_compact_encoding() {
+        let src: String = format!("echo {}", "äöü€".repeat(40));
+        let config: ObfuscatorConfig = ObfuscatorConfig::new()
+            .non_ascii(NonAsciiPolicy::Encode { code_page: 65001 })
+            .max_line_length(800);
+
+        roundtrip(&src, config);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_roundtrip() {
+        let obfuscated: Obfuscated = ObfuscatorConfig::new().seed(5).build().unwrap().obfuscate_str("echo %greeting%").unwrap();
+
+        let json: String = serde_json::to_string(&obfuscated).unwrap();
+        let restored: Obfuscated = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(restored.code, obfuscated.code);
+        assert_eq!(restored.alphabet, obfuscated.alphabet);
+        assert_eq!(restored.diagnostics, obfuscated.diagnostics);
+    }
+}
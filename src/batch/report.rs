@@ -0,0 +1,278 @@
+/* Copyright (c) 2022 Zach Griffin (0xTas)
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE. */
+
+
+
+use std::{
+    fmt,
+    collections::HashMap,
+};
+use crate::batch::{
+    deobfuscator::Layer,
+    emulator::{
+        Definition,
+        Unresolved,
+    },
+};
+
+
+/// A variable which was defined by a layer, but never expanded anywhere in it.<br><br>
+/// *layer* and *line* are both 1-based.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UnusedDefinition {
+    pub layer: usize,
+    pub line: usize,
+    pub name: String,
+}
+
+/// A variable which was defined more than once within a single layer.<br><br>
+/// *conflicting* is *true* if the definitions disagree about its value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DuplicateDefinition {
+    pub layer: usize,
+    pub name: String,
+    pub lines: Vec<usize>,
+    pub conflicting: bool,
+}
+
+
+/// A line which needed more than one expansion round, because it was run through `call`.<br><br>
+/// *layer* and *line* are both 1-based, and *rounds* includes the first expansion.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RepeatedExpansion {
+    pub layer: usize,
+    pub line: usize,
+    pub rounds: usize,
+}
+
+
+/// ### Describes how completely a script was deobfuscated, so that a perfect recovery can be told apart from a partial one.<br>
+/// *unresolved* lists the references left in the final code, with line numbers relative to the input of layer *depth*.<br>
+/// ### Example Usage:<br>
+/// ```
+/// use oxidizer::batch::deobfuscator::BatchDeobfuscator;
+/// 
+/// # fn main() -> oxidizer::Result<()> {
+/// let deobfuscated = BatchDeobfuscator::new().deobfuscate_str("set a=ec\nset a=ho\n%a% %payload%")?;
+/// 
+/// assert_eq!(deobfuscated.report.unresolved[0].name, "payload");
+/// assert_eq!(deobfuscated.report.coverage(), 50.0);
+/// assert!(deobfuscated.report.duplicates[0].conflicting);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+pub struct DeobfuscationReport {
+    pub depth: usize,
+    pub resolved: usize,
+    pub unresolved: Vec<Unresolved>,
+    pub unused: Vec<UnusedDefinition>,
+    pub duplicates: Vec<DuplicateDefinition>,
+    pub reparsed: Vec<RepeatedExpansion>,
+}
+
+impl DeobfuscationReport {
+
+    /// Builds a report from the layers peeled by a BatchDeobfuscator, outermost first.
+    pub fn from_layers(layers: &[Layer]) -> Self {
+        let mut report: DeobfuscationReport = DeobfuscationReport {
+            depth: layers.len(),
+            resolved: layers.iter().map(|layer| layer.resolved).sum(),
+            unresolved: layers.last().map(|layer| layer.unresolved.clone()).unwrap_or_default(),
+            ..DeobfuscationReport::default()
+        };
+
+        for (index, layer) in layers.iter().enumerate() {
+            report.unused.extend(layer.definitions.iter()
+                .filter(|definition| !definition.used)
+                .map(|definition| UnusedDefinition { layer: index + 1, line: definition.line, name: definition.name.clone() }));
+            report.duplicates.extend(find_duplicates(&layer.definitions, index + 1));
+            report.reparsed.extend(layer.reparsed.iter()
+                .map(|reparse| RepeatedExpansion { layer: index + 1, line: reparse.line, rounds: reparse.rounds }));
+        };
+
+        report
+    }
+
+    /// Returns the percentage of variable references which were resolved, or *100* if there were none at all.
+    pub fn coverage(&self) -> f64 {
+        let total: usize = self.resolved + self.unresolved.len();
+
+        if total == 0 {
+            100.0
+        }else {
+            self.resolved as f64 * 100.0 / total as f64
+        }
+    }
+
+    /// Returns *true* if every variable reference was resolved.
+    pub fn is_complete(&self) -> bool {
+        self.unresolved.is_empty()
+    }
+
+    /// Renders the report as a JSON object, exactly as it appears within `--format json` output.
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("A report should always serialize!")
+    }
+}
+
+/// The report is serialized along with its *coverage*, which is derived from the other fields rather than stored.
+#[cfg(feature = "serde")]
+impl serde::Serialize for DeobfuscationReport {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut report = serializer.serialize_struct("DeobfuscationReport", 7)?;
+        report.serialize_field("depth", &self.depth)?;
+        report.serialize_field("resolved", &self.resolved)?;
+        report.serialize_field("coverage", &self.coverage())?;
+        report.serialize_field("unresolved", &self.unresolved)?;
+        report.serialize_field("unused", &self.unused)?;
+        report.serialize_field("duplicates", &self.duplicates)?;
+        report.serialize_field("reparsed", &self.reparsed)?;
+        report.end()
+    }
+}
+
+impl fmt::Display for DeobfuscationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Resolved {} of {} variable references ({:.1}%) across {} layer(s).",
+            self.resolved, self.resolved + self.unresolved.len(), self.coverage(), self.depth)?;
+
+        if !self.unresolved.is_empty() {
+            writeln!(f, "Unresolved references (layer {}):", self.depth)?;
+            for unresolved in &self.unresolved {
+                writeln!(f, "\tline {}, columns {}..{}: {}", unresolved.line, unresolved.span.start, unresolved.span.end, unresolved.name)?;
+            };
+        };
+
+        if !self.unused.is_empty() {
+            writeln!(f, "Unused definitions:")?;
+            for unused in &self.unused {
+                writeln!(f, "\tlayer {}, line {}: {}", unused.layer, unused.line, unused.name)?;
+            };
+        };
+
+        if !self.duplicates.is_empty() {
+            writeln!(f, "Duplicate definitions:")?;
+            for duplicate in &self.duplicates {
+                let conflict: &str = if duplicate.conflicting { " with conflicting values" } else { "" };
+                writeln!(f, "\tlayer {}: {} on lines {}{}", duplicate.layer, duplicate.name, join(&duplicate.lines, ", "), conflict)?;
+            };
+        };
+
+        if !self.reparsed.is_empty() {
+            writeln!(f, "Lines expanded more than once by call:")?;
+            for reparse in &self.reparsed {
+                writeln!(f, "\tlayer {}, line {}: {} rounds", reparse.layer, reparse.line, reparse.rounds)?;
+            };
+        };
+
+        Ok(())
+    }
+}
+
+
+/// Groups the definitions of a layer by name, ignoring case as cmd does, and returns those made more than once.
+fn find_duplicates(definitions: &[Definition], layer: usize) -> Vec<DuplicateDefinition> {
+    let mut groups: Vec<Vec<&Definition>> = Vec::new();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+
+    for definition in definitions {
+        let position: usize = *positions.entry(definition.name.to_lowercase()).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[position].push(definition);
+    };
+
+    groups.into_iter()
+        .filter(|group| group.len() > 1)
+        .map(|group| DuplicateDefinition {
+            layer,
+            name: group[0].name.clone(),
+            lines: group.iter().map(|definition| definition.line).collect(),
+            conflicting: group.iter().any(|definition| definition.value != group[0].value),
+        })
+        .collect()
+}
+
+/// Joins a list of line numbers with *separator*.
+fn join(lines: &[usize], separator: &str) -> String {
+    lines.iter().map(usize::to_string).collect::<Vec<String>>().join(separator)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duplicates_and_unused() {
+        let definition = |line: usize, name: &str, value: &str, used: bool| Definition { line, name: name.to_string(), value: value.to_string(), used };
+        let layer: Layer = Layer {
+            technique: crate::batch::deobfuscator::Technique::Emulation,
+            code: String::new(),
+            unresolved: Vec::new(),
+            substitutions: Vec::new(),
+            definitions: vec![definition(1, "a", "x", true), definition(2, "b", "y", false), definition(3, "A", "x", true), definition(4, "b", "z", true)],
+            reparsed: vec![crate::batch::emulator::Reparse { line: 4, rounds: 2 }],
+            resolved: 3,
+        };
+
+        let report: DeobfuscationReport = DeobfuscationReport::from_layers(&[layer]);
+
+        assert!(report.is_complete());
+        assert_eq!(report.coverage(), 100.0);
+        assert_eq!(report.unused, vec![UnusedDefinition { layer: 1, line: 2, name: String::from("b") }]);
+        assert_eq!(report.duplicates, vec![
+            DuplicateDefinition { layer: 1, name: String::from("a"), lines: vec![1, 3], conflicting: false },
+            DuplicateDefinition { layer: 1, name: String::from("b"), lines: vec![2, 4], conflicting: true },
+        ]);
+        #[cfg(feature = "serde")]
+        assert_eq!(report.to_json(), concat!(
+            r#"{"depth":1,"resolved":3,"coverage":100.0,"unresolved":[],"unused":[{"layer":1,"line":2,"name":"b"}],"#,
+            r#""duplicates":[{"layer":1,"name":"a","lines":[1,3],"conflicting":false},{"layer":1,"name":"b","lines":[2,4],"conflicting":true}],"#,
+            r#""reparsed":[{"layer":1,"line":4,"rounds":2}]}"#,
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_json_matches_the_serialized_report() {
+        use crate::batch::deobfuscator::{
+            BatchDeobfuscator,
+            Deobfuscated,
+        };
+
+        let deobfuscated: Deobfuscated = BatchDeobfuscator::new().deobfuscate_str("set a=ec\nset b=ho\nset b=ho\n%a%%b% %missing%").unwrap();
+
+        let embedded: serde_json::Value = serde_json::to_value(&deobfuscated).unwrap()["report"].clone();
+        let standalone: serde_json::Value = serde_json::from_str(&deobfuscated.report.to_json()).unwrap();
+        assert_eq!(embedded, standalone);
+        assert!(embedded.get("coverage").is_some_and(serde_json::Value::is_number));
+        assert_eq!(serde_json::from_value::<DeobfuscationReport>(embedded).unwrap(), deobfuscated.report);
+    }
+}
@@ -0,0 +1,210 @@
+/* Copyright (c) 2022 Zach Griffin (0xTas)
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE. */
+
+
+use std::fmt;
+use crate::{
+    Result,
+    batch::{
+        obfuscator::{
+            ObfuscatorConfig,
+            Obfuscated,
+        },
+        deobfuscator::BatchDeobfuscator,
+    },
+};
+
+
+/// The largest table of lines *diff_lines* fills in, beyond which only the first differing line is reported.
+const MAX_DIFF_CELLS: usize = 1 << 22;
+
+/// A single line-level difference between an input script and its restored counterpart.<br><br>
+/// Line numbers are 1-based, and refer to the input script for **Missing** lines and the restored script for **Unexpected** lines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LineDiff {
+    /// A line of the input script which did not survive the round trip.
+    Missing { line: usize, text: String },
+    /// A line of the restored script which was not present in the input.
+    Unexpected { line: usize, text: String },
+}
+
+impl fmt::Display for LineDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LineDiff::Missing { line, text } => write!(f, "-{:>5}: {}", line, text),
+            LineDiff::Unexpected { line, text } => write!(f, "+{:>5}: {}", line, text),
+        }
+    }
+}
+
+/// The outcome of obfuscating a script and deobfuscating the result again.
+#[derive(Debug, Clone)]
+pub struct RoundTrip {
+    pub obfuscated: Obfuscated,
+    pub restored: String,
+    pub diff: Vec<LineDiff>,
+}
+
+impl RoundTrip {
+
+    /// Returns *true* if the restored script matches the input line for line.
+    pub fn is_lossless(&self) -> bool {
+        self.diff.is_empty()
+    }
+}
+
+
+/// ### Obfuscates a script with the given configuration, peels the one layer the obfuscator added, and diffs the result against the input.<br>
+/// Lines are compared exactly, including blank lines and carriage returns.<br>
+/// ### Example Usage:<br>
+/// ```
+/// use oxidizer::batch::{
+///     obfuscator::ObfuscatorConfig,
+///     verify::verify_roundtrip,
+/// };
+/// 
+/// # fn main() -> oxidizer::Result<()> {
+/// let round_trip = verify_roundtrip("start C:/Windows/System32/calc.exe", &ObfuscatorConfig::new())?;
+/// 
+/// for diff in &round_trip.diff {
+///     println!("{}", diff);
+/// };
+/// assert!(round_trip.is_lossless());
+/// # Ok(())
+/// # }
+/// ```
+pub fn verify_roundtrip(src: &str, config: &ObfuscatorConfig) -> Result<RoundTrip> {
+    let obfuscated: Obfuscated = config.clone().build()?.obfuscate_str(src)?;
+    // Only the layer the obfuscator added is peeled, since the script may build commands from its own variables too.
+    let restored: String = BatchDeobfuscator::new().max_depth(1).deobfuscate_str(&obfuscated.code)?.code;
+
+    let expected: Vec<&str> = src.split('\n').collect();
+    let actual: Vec<&str> = restored.split('\n').collect();
+    let diff: Vec<LineDiff> = diff_lines(&expected, &actual);
+
+    Ok(RoundTrip { obfuscated, restored, diff })
+}
+
+/// Computes a minimal line-level diff between two scripts using their longest common subsequence.<br><br>
+/// Lines the scripts share at either end are skipped first. If what remains would need more than *MAX_DIFF_CELLS* to compare,
+/// only the first line where they differ is reported.
+fn diff_lines(expected: &[&str], actual: &[&str]) -> Vec<LineDiff> {
+    let prefix: usize = expected.iter().zip(actual).take_while(|(lhs, rhs)| lhs == rhs).count();
+    let suffix: usize = expected[prefix..].iter().rev().zip(actual[prefix..].iter().rev()).take_while(|(lhs, rhs)| lhs == rhs).count();
+    let expected: &[&str] = &expected[prefix..expected.len() - suffix];
+    let actual: &[&str] = &actual[prefix..actual.len() - suffix];
+
+    let mut diff: Vec<LineDiff> = Vec::new();
+    if (expected.len() + 1).saturating_mul(actual.len() + 1) > MAX_DIFF_CELLS {
+        if let Some(text) = actual.first() {
+            diff.push(LineDiff::Unexpected { line: prefix + 1, text: text.to_string() });
+        };
+        if let Some(text) = expected.first() {
+            diff.push(LineDiff::Missing { line: prefix + 1, text: text.to_string() });
+        };
+        return diff;
+    };
+
+    // lcs[i][j] holds the length of the longest common subsequence of expected[i..] and actual[j..].
+    let mut lcs: Vec<Vec<usize>> = vec![vec![0; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            lcs[i][j] = if expected[i] == actual[j] {
+                lcs[i+1][j+1] + 1
+            }else {
+                lcs[i+1][j].max(lcs[i][j+1])
+            };
+        };
+    };
+
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            i += 1;
+            j += 1;
+        }else if j < actual.len() && (i == expected.len() || lcs[i][j+1] >= lcs[i+1][j]) {
+            diff.push(LineDiff::Unexpected { line: prefix + j + 1, text: actual[j].to_string() });
+            j += 1;
+        }else {
+            diff.push(LineDiff::Missing { line: prefix + i + 1, text: expected[i].to_string() });
+            i += 1;
+        };
+    };
+
+    diff
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_lines() {
+        let expected: Vec<&str> = vec!["@echo off", "echo one", "echo two"];
+        let actual: Vec<&str> = vec!["@echo off", "echo 1", "echo two", "pause"];
+
+        assert_eq!(diff_lines(&expected, &expected), Vec::new());
+        assert_eq!(diff_lines(&expected, &actual), vec![
+            LineDiff::Unexpected { line: 2, text: String::from("echo 1") },
+            LineDiff::Missing { line: 2, text: String::from("echo one") },
+            LineDiff::Unexpected { line: 4, text: String::from("pause") },
+        ]);
+    }
+
+    #[test]
+    fn test_diff_large_scripts() {
+        let expected: Vec<String> = (0..20_000).map(|line| format!("echo {}", line)).collect();
+        let expected: Vec<&str> = expected.iter().map(String::as_str).collect();
+
+        // Only the middle of the scripts is compared, so a single changed line is cheap to find.
+        let mut actual: Vec<&str> = expected.clone();
+        actual[12_345] = "echo changed";
+        assert_eq!(diff_lines(&expected, &actual), vec![
+            LineDiff::Unexpected { line: 12_346, text: String::from("echo changed") },
+            LineDiff::Missing { line: 12_346, text: String::from("echo 12345") },
+        ]);
+
+        // Scripts which differ throughout are too large to compare, so only the first difference is reported.
+        let actual: Vec<&str> = expected.iter().rev().copied().collect();
+        assert_eq!(diff_lines(&expected, &actual), vec![
+            LineDiff::Unexpected { line: 1, text: String::from("echo 19999") },
+            LineDiff::Missing { line: 1, text: String::from("echo 0") },
+        ]);
+    }
+
+    #[test]
+    fn test_verify_roundtrip() {
+        let src: &str = "@echo off\nstart C:/Windows/System32/calc.exe\n";
+        let round_trip: RoundTrip = verify_roundtrip(src, &ObfuscatorConfig::new().seed(7)).unwrap();
+
+        assert!(round_trip.is_lossless(), "{:#?}", round_trip.diff);
+        assert_eq!(round_trip.restored, src);
+    }
+
+    #[test]
+    fn test_script_variables_survive_verification() {
+        let src: &str = "set a=ec\nset b=ho\n%a%%b% hello";
+        let round_trip: RoundTrip = verify_roundtrip(src, &ObfuscatorConfig::new().seed(1)).unwrap();
+
+        assert!(round_trip.is_lossless(), "{:#?}", round_trip.diff);
+    }
+}
@@ -19,16 +19,46 @@ OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 SOFTWARE. */
 
 
-use clap::Parser;
+use clap::{
+    Parser,
+    ValueEnum,
+};
 use std::{
     fs,
-    process::exit
+    io::ErrorKind,
+    process::exit,
+    sync::Arc,
+    time::{
+        Duration,
+        Instant,
+    },
 };
 use oxidizer::{
     wait,
+    input,
+    Error,
     batch::{
-        obfuscator::BatchObfuscator,
-        deobfuscator::BatchDeobfuscator,
+        obfuscator::{
+            BatchObfuscator,
+            NonAsciiPolicy,
+            ObfuscatorConfig,
+            Obfuscated,
+        },
+        deobfuscator::{
+            BatchDeobfuscator,
+            Deobfuscated,
+            DEFAULT_MAX_DEPTH,
+        },
+        emulator::Environment,
+        encoding::Encoding,
+        diagnostic::{
+            Diagnostic,
+            DiagnosticPolicy,
+        },
+        verify::{
+            RoundTrip,
+            verify_roundtrip,
+        },
     },
 };
 
@@ -62,58 +92,317 @@ struct Args {
 
     /// Maximum obfuscated variable length
     #[arg(long)]
-    max: Option<u32>
+    max: Option<u32>,
+
+    /// Seed for reproducible obfuscation output
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Deobfuscate the output again and fail if it does not match the input
+    #[arg(long, default_value_t = false)]
+    verify: bool,
+
+    /// Also obfuscate non-ASCII characters, switching the console to this code page first (437, 1252 or 65001 for UTF-8)
+    #[arg(long, value_parser = parse_code_page)]
+    code_page: Option<u32>,
+
+    /// File of NAME=VALUE lines overriding the default Windows environment used when deobfuscating
+    #[arg(long)]
+    env_file: Option<String>,
+
+    /// Maximum number of obfuscation layers to peel when deobfuscating
+    #[arg(long, default_value_t = DEFAULT_MAX_DEPTH)]
+    max_depth: usize,
+
+    /// Also strip caret-escape and quote noise (such as p^o^w^e^r^s^h^e^l^l) when deobfuscating
+    #[arg(long, default_value_t = false)]
+    normalize: bool,
+
+    /// Print the code revealed by every deobfuscated layer, not just the techniques peeled
+    #[arg(long, default_value_t = false)]
+    show_layers: bool,
+
+    /// Print a human-readable summary, or a single JSON object with the output script, alphabet, diagnostics and timing
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// Print a report of unresolved references, coverage and unused or duplicate definitions after deobfuscating
+    #[arg(long, value_enum)]
+    report: Option<ReportFormat>,
+
+    /// Encoding of the input file (utf-8, utf-8-bom, utf-16le, utf-16be, cp437 or cp1252), detected when omitted
+    #[arg(long, value_parser = parse_encoding)]
+    encoding: Option<Encoding>,
+
+    /// Encoding of the output file, which defaults to the --code-page encoding or else the input encoding (UTF-16 input is written as UTF-8, since cmd.exe cannot run it)
+    #[arg(long, value_parser = parse_encoding)]
+    output_encoding: Option<Encoding>,
 }
 
 
+/// The ways the result of a run can be printed.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// The ways a deobfuscation report can be printed.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ReportFormat {
+    Text,
+    Json,
+}
+
+
+/// Exit code used when round-trip verification finds differences.
+const EXIT_VERIFY_FAILED: i32 = 2;
+
+
 fn main() {
     let mut args = Args::parse();
 
     if let Some(min_value) = args.min {
         if let Some(max_value) = args.max {
             if min_value > max_value {
-                args.max = Some(args.min.unwrap() * 2);
+                args.max = Some(min_value.saturating_mul(2));
             };
         };
     };
 
+    let json: bool = args.format == OutputFormat::Json;
+    if (json || matches!(args.report, Some(ReportFormat::Json))) && !cfg!(feature = "serde") {
+        eprintln!("\nJSON output requires the \"serde\" feature!");
+        exit(1);
+    };
+
     if args.deobfuscate {
-        let mut deobfuscator: BatchDeobfuscator = BatchDeobfuscator::new();
+        let mut environment: Environment = Environment::windows_defaults();
+        if let Some(env_file) = &args.env_file {
+            match fs::read_to_string(env_file) {
+                Ok(overrides) => environment.load(&overrides),
+                Err(err) => bail(Error::from(err)),
+            };
+        };
+        let mut deobfuscator: BatchDeobfuscator = BatchDeobfuscator::with_environment(environment).max_depth(args.max_depth).normalize(args.normalize);
 
-        if let Ok(contents) = fs::read_to_string(args.input.trim_end()) {
-            deobfuscator.initialize(contents);
-        }else {
-            println!("\nError! File not found.");
-            println!("Please provide a valid path to an obfuscated file!");
-            wait(4200);
-            exit(1);
+        let started: Instant = Instant::now();
+        let (deobfuscated, input_encoding): (Deobfuscated, Encoding) = match fs::read(args.input.trim_end()) {
+            Ok(bytes) => {
+                let encoding: Encoding = args.encoding.unwrap_or_else(|| Encoding::detect(&bytes));
+                match encoding.decode(&bytes).and_then(|contents| deobfuscator.deobfuscate_str(&contents)) {
+                    Ok(deobfuscated) => (deobfuscated, encoding),
+                    Err(err) => bail(err),
+                }
+            },
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                println!("\nError! File not found.");
+                println!("Please provide a valid path to an obfuscated file!");
+                wait(4200);
+                exit(1);
+            },
+            Err(err) => bail(Error::from(err)),
         };
 
-        let path: String = deobfuscator.write_deobfuscated_script(args.output_file);
-        println!("\nDumped deobfuscated output to file: {}\nDeobfuscation Complete.", path);
+        let path: String = args.output_file.unwrap_or_else(|| String::from("deobfuscated.bat"));
+        let output_encoding: Encoding = args.output_encoding.unwrap_or_else(|| runnable_encoding(input_encoding));
+        match write_script(&path, &deobfuscated.code, output_encoding) {
+            Ok(()) if json => print_json("deobfuscate", &path, started.elapsed(), &deobfuscated),
+            Ok(()) => println!("\nDumped deobfuscated output to file: {}\nDeobfuscation Complete.", path),
+            Err(err) => bail(err),
+        };
+
+        if json {
+            exit(0);
+        };
+
+        println!("\nPeeled {} layer(s):", deobfuscator.layers.len());
+        for (depth, layer) in deobfuscator.layers.iter().enumerate() {
+            println!("\tlayer {}: {}", depth + 1, layer.technique);
+            for substitution in &layer.substitutions {
+                println!("\t\tline {}: {} -> {}", substitution.line, substitution.expression, substitution.result);
+            };
+            if args.show_layers {
+                for line in layer.code.lines() {
+                    println!("\t\t| {}", line);
+                };
+            };
+        };
+
+        match args.report {
+            Some(ReportFormat::Text) => print!("\n{}", deobfuscator.report),
+            #[cfg(feature = "serde")]
+            Some(ReportFormat::Json) => println!("{}", deobfuscator.report.to_json()),
+            #[cfg(not(feature = "serde"))]
+            Some(ReportFormat::Json) => unreachable!("JSON reports require the serde feature"),
+            None => println!("\nResolved {:.1}% of variable references.", deobfuscator.report.coverage()),
+        };
 
         exit(0);
     }else {
-        let mut obfuscator: BatchObfuscator = BatchObfuscator::new();
-        if args.echo_off {
-            obfuscator.dont_echo();
+        let mut config: ObfuscatorConfig = ObfuscatorConfig::new()
+            .name_length(args.min.unwrap_or(7), args.max.unwrap_or(109))
+            .echo_off(args.echo_off);
+        if let Some(seed) = args.seed {
+            config = config.seed(seed);
         };
-        if args.yeet_it {
-            obfuscator.dont_warn();
+        if let Some(code_page) = args.code_page {
+            config = config.non_ascii(NonAsciiPolicy::Encode { code_page });
         };
+        // There is nobody to answer a prompt when the output is being consumed as JSON, and the diagnostics are part of it anyway.
+        if !args.yeet_it && !json {
+            config = config.diagnostic_policy(DiagnosticPolicy::Ask(Arc::new(confirm_diagnostics)));
+        };
+        let obfuscator: BatchObfuscator = match config.build() {
+            Ok(obfuscator) => obfuscator,
+            Err(err) => bail(err),
+        };
+
+        // Anything that isn't a readable file is obfuscated as an inline command.
+        let (src, input_encoding): (String, Encoding) = match fs::read(args.input.trim_end()) {
+            Ok(bytes) => {
+                let encoding: Encoding = args.encoding.unwrap_or_else(|| Encoding::detect(&bytes));
+                match encoding.decode(&bytes) {
+                    Ok(contents) => (contents, encoding),
+                    Err(err) => bail(err),
+                }
+            },
+            Err(_) => (args.input, Encoding::Utf8),
+        };
+        let started: Instant = Instant::now();
+        let obfuscated: Obfuscated = if args.verify {
+            let round_trip: RoundTrip = match verify_roundtrip(&src, obfuscator.config()) {
+                Ok(round_trip) => round_trip,
+                Err(err) => bail(err),
+            };
 
-        if let Ok(contents) = fs::read_to_string(args.input.trim_end()) {
-            obfuscator.initialize(args.min, args.max, contents);
+            if !round_trip.is_lossless() {
+                eprintln!("\nVerification failed! The deobfuscated output differs from the input:\n");
+                for diff in &round_trip.diff {
+                    eprintln!("{}", diff);
+                };
+                exit(EXIT_VERIFY_FAILED);
+            };
 
-            let path: String = obfuscator.write_obfuscated_script(args.output_file);
-            println!("\nDumped obfuscated output to file: {}\nObfuscation Complete.", path);
+            if !json {
+                println!("\nVerification passed: the output deobfuscates back to the input.");
+            };
+            round_trip.obfuscated
         }else {
-            obfuscator.initialize(args.min, args.max, args.input);
+            match obfuscator.obfuscate_str(&src) {
+                Ok(obfuscated) => obfuscated,
+                Err(err) => bail(err),
+            }
+        };
 
-            let path: String = obfuscator.write_obfuscated_script(args.output_file);
-            println!("\nDumped obfuscated output to file: {}\nObfuscation Complete.", path);
+        let path: String = args.output_file.unwrap_or_else(|| String::from("obfuscated.bat"));
+        let output_encoding: Encoding = match (args.output_encoding, args.code_page.and_then(Encoding::from_code_page)) {
+            // The bytes written must be the ones the console reads after the `chcp` line.
+            (Some(output_encoding), Some(code_page_encoding)) if output_encoding != code_page_encoding => {
+                eprintln!("\n--output-encoding {} does not match --code-page {}, which reads {}!", output_encoding, args.code_page.unwrap_or_default(), code_page_encoding);
+                exit(1);
+            },
+            (Some(output_encoding), _) => output_encoding,
+            (None, Some(code_page_encoding)) => code_page_encoding,
+            (None, None) => runnable_encoding(input_encoding),
+        };
+        match write_script(&path, &obfuscated.code, output_encoding) {
+            Ok(()) if json => print_json("obfuscate", &path, started.elapsed(), &obfuscated),
+            Ok(()) => println!("\nDumped obfuscated output to file: {}\nObfuscation Complete.", path),
+            Err(err) => bail(err),
         };
 
         exit(0);
     };
-}
\ No newline at end of file
+}
+
+
+/// Warns the user about constructs which cannot be obfuscated, and asks whether to continue anyway.
+fn confirm_diagnostics(diagnostics: &[Diagnostic]) -> bool {
+    println!("\n[!]--> WARNING: Because of the way this obfuscation method works, 
+        variables you define or use in your scripts, including environment variables,
+        and function labels, cannot be effectively obfuscated using this obfuscation method, 
+        so variable references, labels and set lines will be printed as-is in order to preserve functionality.\n");
+
+    for diagnostic in diagnostics {
+        println!("\t{}", diagnostic);
+    };
+
+    match input("\nContinue Anyway? [Y/N] ~> ") {
+        Ok(heed) => heed.to_lowercase().contains('y'),
+        Err(_) => false,
+    }
+}
+
+/// Parses an *--encoding* label for clap.
+fn parse_encoding(label: &str) -> Result<Encoding, String> {
+    Encoding::from_label(label).ok_or_else(|| format!("unknown encoding: {}", label))
+}
+
+/// Parses a *--code-page* for clap, accepting only code pages whose encoding the output can be written in.
+fn parse_code_page(code_page: &str) -> Result<u32, String> {
+    let code_page: u32 = code_page.parse().map_err(|_| format!("not a code page: {}", code_page))?;
+
+    match Encoding::from_code_page(code_page) {
+        Some(_) => Ok(code_page),
+        None => Err(format!("code page {} cannot be written, use 437, 1252 or 65001", code_page)),
+    }
+}
+
+/// Picks an encoding cmd.exe can run for output written in the same encoding as the input.
+fn runnable_encoding(encoding: Encoding) -> Encoding {
+    match encoding {
+        Encoding::Utf16Le | Encoding::Utf16Be => Encoding::Utf8,
+        _ => encoding,
+    }
+}
+
+/// Writes a script to *path* in the given encoding, leaving its line endings untouched.
+fn write_script(path: &str, code: &str, encoding: Encoding) -> Result<(), Error> {
+    let bytes: Vec<u8> = encoding.encode(code)?;
+    fs::write(path, bytes)?;
+
+    Ok(())
+}
+
+/// Prints the result of a run as a single JSON object, alongside the output file and how long the run took.
+#[cfg(feature = "serde")]
+fn print_json<T: serde::Serialize>(mode: &str, output_file: &str, elapsed: Duration, result: &T) {
+    #[derive(serde::Serialize)]
+    struct Summary<'a, T: serde::Serialize> {
+        mode: &'a str,
+        output_file: &'a str,
+        elapsed_ms: f64,
+        #[serde(flatten)]
+        result: &'a T,
+    }
+
+    let summary: Summary<T> = Summary { mode, output_file, elapsed_ms: elapsed.as_secs_f64() * 1000.0, result };
+    match serde_json::to_string(&summary) {
+        Ok(json) => println!("{}", json),
+        Err(err) => {
+            eprintln!("\nFailed to serialize output: {}", err);
+            exit(1);
+        },
+    };
+}
+
+/// JSON output is rejected up front when the serde feature is disabled, so this is never reached.
+#[cfg(not(feature = "serde"))]
+fn print_json<T>(_mode: &str, _output_file: &str, _elapsed: Duration, _result: &T) {
+    unreachable!("JSON output requires the serde feature");
+}
+
+/// Reports a library error to the user and terminates with an appropriate exit code.
+fn bail(err: Error) -> ! {
+    match err {
+        Error::Aborted => {
+            println!("\n{}", err);
+            exit(0);
+        },
+        _ => {
+            eprintln!("\n{}", err);
+            exit(1);
+        },
+    };
+}
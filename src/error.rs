@@ -0,0 +1,91 @@
+/* Copyright (c) 2022 Zach Griffin (0xTas)
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE. */
+
+
+use std::{
+    fmt,
+    io,
+};
+use crate::batch::{
+    diagnostic::Diagnostic,
+    encoding::Encoding,
+};
+
+
+/// A convenience alias for results returned by the (de)obfuscators.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Errors that can occur while (de)obfuscating a script.<br><br>
+/// The library never terminates the process on its own; these are handed back to the caller instead.
+#[derive(Debug)]
+pub enum Error {
+    /// The input script does not appear to be compatible with deobfuscation.
+    IncompatibleInput,
+    /// The object must first be initialized before its output can be used.
+    Uninitialized,
+    /// Reading from or writing to a file or stream failed.
+    Io(io::Error),
+    /// The user declined to continue after being warned about the input.
+    Aborted,
+    /// A character was requested which has no entry in the obfuscated alphabet.
+    UnknownAlphabetKey(char),
+    /// An obfuscated line would exceed cmd.exe's line length limit.
+    LineTooLong(Diagnostic),
+    /// The input is not valid in the encoding it was read with.
+    Decode(Encoding),
+    /// A character cannot be represented in the encoding the output is written with.
+    Encode(char, Encoding),
+    /// No unused variable name could be generated within the configured name lengths.
+    NamesExhausted,
+    /// Output cannot be written in the code page the obfuscator was asked to switch the console to.
+    UnsupportedCodePage(u32),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::IncompatibleInput => write!(f, "Input script does not appear to be compatible with deobfuscation!"),
+            Error::Uninitialized => write!(f, "Object must first be initialized!"),
+            Error::Io(err) => write!(f, "I/O failure: {}", err),
+            Error::Aborted => write!(f, "Obfuscation aborted!"),
+            Error::UnknownAlphabetKey(chr) => write!(f, "Key not in alphabet: {:?}", chr),
+            Error::LineTooLong(diagnostic) => write!(f, "Line too long to obfuscate: {}", diagnostic),
+            Error::Decode(encoding) => write!(f, "Input is not valid {}!", encoding),
+            Error::Encode(chr, encoding) => write!(f, "Character {:?} cannot be written as {}!", chr, encoding),
+            Error::NamesExhausted => write!(f, "Ran out of unique variable names, try a wider name length range!"),
+            Error::UnsupportedCodePage(code_page) => write!(f, "Code page {} cannot be written, use 437, 1252 or 65001!", code_page),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
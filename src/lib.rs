@@ -25,22 +25,23 @@ use std::{
     thread::sleep,
 };
 pub mod batch;
+mod error;
+
+pub use error::{Error, Result};
 
 
 /* General Utility Functions */
 
 /// Prompts the user for input and returns it as a String.<br><br>
 /// The *prompt: &str* parameter is printed without a trailing newline by default. Supply your own if desired.
-pub fn input(prompt: &str) -> String {
+pub fn input(prompt: &str) -> Result<String> {
     let mut user_input: String = String::new();
 
     print!("{}", prompt);
-    io::stdout().flush().expect("Write to console failed!");
-    io::stdin()
-        .read_line(&mut user_input)
-        .expect("Read from stdin failed!");
+    io::stdout().flush()?;
+    io::stdin().read_line(&mut user_input)?;
 
-    user_input
+    Ok(user_input)
 }
 
 /// Sleeps for the given time in milliseconds.
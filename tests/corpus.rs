@@ -0,0 +1,88 @@
+/* Copyright (c) 2022 Zach Griffin (0xTas)
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE. */
+
+//! Corpus tests over real-world batch scripts in `tests/corpus/input`.
+//!
+//! Each script is obfuscated with a fixed seed and compared against `tests/corpus/obfuscated`,
+//! then that golden output is deobfuscated and compared against `tests/corpus/expected`.
+//! Run with `OXIDIZER_BLESS=1` to regenerate the golden files after an intentional change.
+
+use std::{
+    env,
+    fs,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+use oxidizer::batch::{
+    obfuscator::ObfuscatorConfig,
+    deobfuscator::BatchDeobfuscator,
+};
+
+
+const SEED: u64 = 1337;
+
+
+/// Compares *actual* against the golden file at *path*, or overwrites it when blessing.
+fn check_golden(path: &Path, actual: &str) -> Result<(), String> {
+    if env::var_os("OXIDIZER_BLESS").is_some() {
+        fs::write(path, actual).map_err(|err| format!("{}: {}", path.display(), err))?;
+        return Ok(());
+    };
+
+    let expected: String = fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    if expected != actual {
+        return Err(format!("{} does not match (rerun with OXIDIZER_BLESS=1 if the change is intended)", path.display()));
+    };
+
+    Ok(())
+}
+
+#[test]
+fn corpus_matches_golden_files() {
+    let root: PathBuf = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("corpus");
+    let obfuscator = ObfuscatorConfig::new().name_length(4, 8).seed(SEED).build().unwrap();
+
+    let mut inputs: Vec<PathBuf> = fs::read_dir(root.join("input")).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "bat"))
+        .collect();
+    inputs.sort();
+    assert!(!inputs.is_empty(), "corpus is empty");
+
+    let mut failures: Vec<String> = Vec::new();
+    for input in inputs {
+        let file_name = input.file_name().unwrap();
+        let src: String = fs::read_to_string(&input).unwrap();
+
+        let obfuscated: String = obfuscator.obfuscate_str(&src).unwrap().code;
+        if let Err(failure) = check_golden(&root.join("obfuscated").join(file_name), &obfuscated) {
+            failures.push(failure);
+        };
+
+        let restored: String = BatchDeobfuscator::new().deobfuscate_str(&obfuscated).unwrap().code;
+        if let Err(failure) = check_golden(&root.join("expected").join(file_name), &restored) {
+            failures.push(failure);
+        };
+    };
+
+    assert!(failures.is_empty(), "{:#?}", failures);
+}
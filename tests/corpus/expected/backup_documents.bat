@@ -0,0 +1,15 @@
+@echo off
+setlocal
+set SOURCE=%USERPROFILE%\Documents
+set DEST=D:\Backups\Documents
+
+if not exist "%DEST%" mkdir "%DEST%"
+robocopy "%SOURCE%" "%DEST%" /MIR /R:2 /W:5 /LOG:"%DEST%\backup.log"
+if %ERRORLEVEL% GEQ 8 goto :failed
+
+echo Backup completed successfully.
+goto :eof
+
+:failed
+echo Backup failed with code %ERRORLEVEL%, see the log for details.
+exit /b 1
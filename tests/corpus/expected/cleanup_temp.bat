@@ -0,0 +1,11 @@
+@echo off
+:: Clears out temporary files for the current user and the system.
+title Temp Cleanup
+echo Cleaning up temporary files...
+
+del /q /f /s "%TEMP%\*" >nul 2>&1
+for /d %%d in ("%TEMP%\*") do rd /s /q "%%d" 2>nul
+del /q /f /s C:\Windows\Temp\* >nul 2>&1
+
+echo Done!
+pause
@@ -0,0 +1,21 @@
+@echo off
+:menu
+cls
+echo 1) Open Notepad
+echo 2) Open Calculator
+echo 3) Quit
+choice /c 123 /n /m "Select an option: "
+if errorlevel 3 goto :quit
+if errorlevel 2 goto :calc
+if errorlevel 1 goto :notepad
+
+:notepad
+start notepad.exe
+goto :menu
+
+:calc
+start C:/Windows/System32/calc.exe
+goto :menu
+
+:quit
+echo Goodbye^!
@@ -0,0 +1,9 @@
+@echo off
+echo ==== Network Information ====
+hostname
+ipconfig /all | findstr /i "IPv4 Subnet Gateway DNS"
+echo.
+echo ==== Active Connections ====
+netstat -ano | find "ESTABLISHED"
+echo.
+ping -n 2 8.8.8.8 >nul && echo Internet: reachable || echo Internet: unreachable
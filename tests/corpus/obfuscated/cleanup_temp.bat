@@ -0,0 +1,109 @@
+:: VGhpcyBmaWxlIHdhcyBvYmZ1c2NhdGVkIHZpYSBodHRwczovL2dpdGh1Yi5jb20vMHhUYXMvMHhpZGl6M3I=
+:: VGhpcyBmaWxlIGNhbiBiZSBwcm9ncmFtYXRpY2FsbHkgZGVvYmZ1c2NhdGVkIHZpYSBodHRwczovL2dpdGh1Yi5jb20vMHhUYXMvMHhpZGl6M3I=
+set IlY~HOzI=set
+%IlY~HOzI% JNO.!G= 
+%IlY~HOzI%%JNO.!G%ENg]W==
+%IlY~HOzI%%JNO.!G%hL)-i%ENg]W%a
+%IlY~HOzI%%JNO.!G%TJ,bP%ENg]W%b
+%IlY~HOzI%%JNO.!G%E2p[Y%ENg]W%c
+%IlY~HOzI%%JNO.!G%WvXStVMc%ENg]W%d
+%IlY~HOzI%%JNO.!G%Sq#y(a%ENg]W%e
+%IlY~HOzI%%JNO.!G%nef6QY%ENg]W%f
+%IlY~HOzI%%JNO.!G%lfXyeaW%ENg]W%g
+%IlY~HOzI%%JNO.!G%q(_D%ENg]W%h
+%IlY~HOzI%%JNO.!G%iSNsu%ENg]W%i
+%IlY~HOzI%%JNO.!G%Nh#NV%ENg]W%j
+%IlY~HOzI%%JNO.!G%PlB9EdGC%ENg]W%k
+%IlY~HOzI%%JNO.!G%p$Xo-E%ENg]W%l
+%IlY~HOzI%%JNO.!G%ut-}e)h%ENg]W%m
+%IlY~HOzI%%JNO.!G%Rfy2MM%ENg]W%n
+%IlY~HOzI%%JNO.!G%Rh-.)X0A%ENg]W%o
+%IlY~HOzI%%JNO.!G%XS*Q%ENg]W%p
+%IlY~HOzI%%JNO.!G%CJr*hunS%ENg]W%q
+%IlY~HOzI%%JNO.!G%WR4N9(pT%ENg]W%r
+%IlY~HOzI%%JNO.!G%rHRI1QFj%ENg]W%s
+%IlY~HOzI%%JNO.!G%bOt*hRIl%ENg]W%t
+%IlY~HOzI%%JNO.!G%MFAr%ENg]W%u
+%IlY~HOzI%%JNO.!G%aV}sS,H%ENg]W%v
+%IlY~HOzI%%JNO.!G%tf~geU%ENg]W%w
+%IlY~HOzI%%JNO.!G%VAqfb%ENg]W%x
+%IlY~HOzI%%JNO.!G%Q5t?uGAS%ENg]W%y
+%IlY~HOzI%%JNO.!G%ge5FxIN%ENg]W%z
+%IlY~HOzI%%JNO.!G%M9lS%ENg]W%A
+%IlY~HOzI%%JNO.!G%M!!.S%ENg]W%B
+%IlY~HOzI%%JNO.!G%kOiy%ENg]W%C
+%IlY~HOzI%%JNO.!G%ltQ3e{v%ENg]W%D
+%IlY~HOzI%%JNO.!G%u7u?P%ENg]W%E
+%IlY~HOzI%%JNO.!G%UkS3IU4Z%ENg]W%F
+%IlY~HOzI%%JNO.!G%qllG%ENg]W%G
+%IlY~HOzI%%JNO.!G%lGxB%ENg]W%H
+%IlY~HOzI%%JNO.!G%Sa6u%ENg]W%I
+%IlY~HOzI%%JNO.!G%YA[QUl%ENg]W%J
+%IlY~HOzI%%JNO.!G%i$LLqRZ%ENg]W%K
+%IlY~HOzI%%JNO.!G%qmyIFu%ENg]W%L
+%IlY~HOzI%%JNO.!G%iVORiKC%ENg]W%M
+%IlY~HOzI%%JNO.!G%pudv8vyE%ENg]W%N
+%IlY~HOzI%%JNO.!G%yg6_Gdk%ENg]W%O
+%IlY~HOzI%%JNO.!G%H{,w9J%ENg]W%P
+%IlY~HOzI%%JNO.!G%H{3B%ENg]W%Q
+%IlY~HOzI%%JNO.!G%C!et[zI%ENg]W%R
+%IlY~HOzI%%JNO.!G%m?[~.xs%ENg]W%S
+%IlY~HOzI%%JNO.!G%J9A4QIL%ENg]W%T
+%IlY~HOzI%%JNO.!G%XIpj%ENg]W%U
+%IlY~HOzI%%JNO.!G%N{B?bo%ENg]W%V
+%IlY~HOzI%%JNO.!G%s)#QV%ENg]W%W
+%IlY~HOzI%%JNO.!G%h$4Vy%ENg]W%X
+%IlY~HOzI%%JNO.!G%aub*z~h%ENg]W%Y
+%IlY~HOzI%%JNO.!G%z1jOl%ENg]W%Z
+%IlY~HOzI%%JNO.!G%F2LzaJc%ENg]W%0
+%IlY~HOzI%%JNO.!G%Odnjfa%ENg]W%1
+%IlY~HOzI%%JNO.!G%cb0!uNz%ENg]W%2
+%IlY~HOzI%%JNO.!G%q(ZY%ENg]W%3
+%IlY~HOzI%%JNO.!G%ra#sy%ENg]W%4
+%IlY~HOzI%%JNO.!G%e-(Yh%ENg]W%5
+%IlY~HOzI%%JNO.!G%Sovd%ENg]W%6
+%IlY~HOzI%%JNO.!G%VcPW%ENg]W%7
+%IlY~HOzI%%JNO.!G%YpqGt$Y%ENg]W%8
+%IlY~HOzI%%JNO.!G%jgsh-t%ENg]W%9
+%IlY~HOzI%%JNO.!G%UC*O%ENg]W%!
+%IlY~HOzI%%JNO.!G%V52Q%ENg]W%"
+%IlY~HOzI%%JNO.!G%R{$z%ENg]W%#
+%IlY~HOzI%%JNO.!G%nMcb%ENg]W%$
+%IlY~HOzI%%JNO.!G%u4tw%ENg]W%'
+%IlY~HOzI%%JNO.!G%d[XCg%ENg]W%(
+%IlY~HOzI%%JNO.!G%zh~n%ENg]W%)
+%IlY~HOzI%%JNO.!G%LC5L%ENg]W%*
+%IlY~HOzI%%JNO.!G%AhsX%ENg]W%+
+%IlY~HOzI%%JNO.!G%b.{He%ENg]W%,
+%IlY~HOzI%%JNO.!G%aKqk%ENg]W%-
+%IlY~HOzI%%JNO.!G%DWdP%ENg]W%.
+%IlY~HOzI%%JNO.!G%hN9L%ENg]W%/
+%IlY~HOzI%%JNO.!G%bk,1oJ%ENg]W%:
+%IlY~HOzI%%JNO.!G%mwZl$i%ENg]W%;
+%IlY~HOzI%%JNO.!G%Ga3)iNb%ENg]W%=
+%IlY~HOzI%%JNO.!G%iF2DBR%ENg]W%?
+%IlY~HOzI%%JNO.!G%WnA5sS9S%ENg]W%@
+%IlY~HOzI%%JNO.!G%OgHK%ENg]W%[
+%IlY~HOzI%%JNO.!G%k.xfNe%ENg]W%\
+%IlY~HOzI%%JNO.!G%c_Q7Zsf%ENg]W%]
+%IlY~HOzI%%JNO.!G%X3zrg3~k%ENg]W%_
+%IlY~HOzI%%JNO.!G%Dze9J%ENg]W%`
+%IlY~HOzI%%JNO.!G%AE_M8,J%ENg]W%{
+%IlY~HOzI%%JNO.!G%ZoWm%ENg]W%}
+%IlY~HOzI%%JNO.!G%g6~n%ENg]W%~
+%IlY~HOzI%%JNO.!G%WYJU%ENg]W% 
+%WnA5sS9S%%Sq#y(a%%E2p[Y%%q(_D%%Rh-.)X0A%%WYJU%%Rh-.)X0A%%nef6QY%%nef6QY%
+%bk,1oJ%%bk,1oJ%%WYJU%%kOiy%%p$Xo-E%%Sq#y(a%%hL)-i%%WR4N9(pT%%rHRI1QFj%%WYJU%%Rh-.)X0A%%MFAr%%bOt*hRIl%%WYJU%%bOt*hRIl%%Sq#y(a%%ut-}e)h%%XS*Q%%Rh-.)X0A%%WR4N9(pT%%hL)-i%%WR4N9(pT%%Q5t?uGAS%%WYJU%%nef6QY%%iSNsu%%p$Xo-E%%Sq#y(a%%rHRI1QFj%%WYJU%%nef6QY%%Rh-.)X0A%%WR4N9(pT%%WYJU%%bOt*hRIl%%q(_D%%Sq#y(a%%WYJU%%E2p[Y%%MFAr%%WR4N9(pT%%WR4N9(pT%%Sq#y(a%%Rfy2MM%%bOt*hRIl%%WYJU%%MFAr%%rHRI1QFj%%Sq#y(a%%WR4N9(pT%%WYJU%%hL)-i%%Rfy2MM%%WvXStVMc%%WYJU%%bOt*hRIl%%q(_D%%Sq#y(a%%WYJU%%rHRI1QFj%%Q5t?uGAS%%rHRI1QFj%%bOt*hRIl%%Sq#y(a%%ut-}e)h%%DWdP%
+%bOt*hRIl%%iSNsu%%bOt*hRIl%%p$Xo-E%%Sq#y(a%%WYJU%%J9A4QIL%%Sq#y(a%%ut-}e)h%%XS*Q%%WYJU%%kOiy%%p$Xo-E%%Sq#y(a%%hL)-i%%Rfy2MM%%MFAr%%XS*Q%
+%Sq#y(a%%E2p[Y%%q(_D%%Rh-.)X0A%%WYJU%%kOiy%%p$Xo-E%%Sq#y(a%%hL)-i%%Rfy2MM%%iSNsu%%Rfy2MM%%lfXyeaW%%WYJU%%MFAr%%XS*Q%%WYJU%%bOt*hRIl%%Sq#y(a%%ut-}e)h%%XS*Q%%Rh-.)X0A%%WR4N9(pT%%hL)-i%%WR4N9(pT%%Q5t?uGAS%%WYJU%%nef6QY%%iSNsu%%p$Xo-E%%Sq#y(a%%rHRI1QFj%%DWdP%%DWdP%%DWdP%
+
+%WvXStVMc%%Sq#y(a%%p$Xo-E%%WYJU%%hN9L%%CJr*hunS%%WYJU%%hN9L%%nef6QY%%WYJU%%hN9L%%rHRI1QFj%%WYJU%%V52Q%%TEMP%%k.xfNe%%LC5L%%V52Q%%WYJU%>%Rfy2MM%%MFAr%%p$Xo-E%%WYJU%%cb0!uNz%>&%Odnjfa%
+%nef6QY%%Rh-.)X0A%%WR4N9(pT%%WYJU%%hN9L%%WvXStVMc%%WYJU%%%d%WYJU%%iSNsu%%Rfy2MM%%WYJU%%d[XCg%%V52Q%%TEMP%%k.xfNe%%LC5L%%V52Q%%zh~n%%WYJU%%WvXStVMc%%Rh-.)X0A%%WYJU%%WR4N9(pT%%WvXStVMc%%WYJU%%hN9L%%rHRI1QFj%%WYJU%%hN9L%%CJr*hunS%%WYJU%%V52Q%%%d%V52Q%%WYJU%%cb0!uNz%>%Rfy2MM%%MFAr%%p$Xo-E%
+%WvXStVMc%%Sq#y(a%%p$Xo-E%%WYJU%%hN9L%%CJr*hunS%%WYJU%%hN9L%%nef6QY%%WYJU%%hN9L%%rHRI1QFj%%WYJU%%kOiy%%bk,1oJ%%k.xfNe%%s)#QV%%iSNsu%%Rfy2MM%%WvXStVMc%%Rh-.)X0A%%tf~geU%%rHRI1QFj%%k.xfNe%%J9A4QIL%%Sq#y(a%%ut-}e)h%%XS*Q%%k.xfNe%%LC5L%%WYJU%>%Rfy2MM%%MFAr%%p$Xo-E%%WYJU%%cb0!uNz%>&%Odnjfa%
+
+%Sq#y(a%%E2p[Y%%q(_D%%Rh-.)X0A%%WYJU%%ltQ3e{v%%Rh-.)X0A%%Rfy2MM%%Sq#y(a%%UC*O%
+%XS*Q%%hL)-i%%MFAr%%rHRI1QFj%%Sq#y(a%
+
+
+:: VGhpcyBmaWxlIHdhcyBvYmZ1c2NhdGVkIHZpYSBodHRwczovL2dpdGh1Yi5jb20vMHhUYXMvMHhpZGl6M3I=
+:: VGhpcyBmaWxlIGNhbiBiZSBwcm9ncmFtYXRpY2FsbHkgZGVvYmZ1c2NhdGVkIHZpYSBodHRwczovL2dpdGh1Yi5jb20vMHhUYXMvMHhpZGl6M3I=
\ No newline at end of file
@@ -0,0 +1,119 @@
+:: VGhpcyBmaWxlIHdhcyBvYmZ1c2NhdGVkIHZpYSBodHRwczovL2dpdGh1Yi5jb20vMHhUYXMvMHhpZGl6M3I=
+:: VGhpcyBmaWxlIGNhbiBiZSBwcm9ncmFtYXRpY2FsbHkgZGVvYmZ1c2NhdGVkIHZpYSBodHRwczovL2dpdGh1Yi5jb20vMHhUYXMvMHhpZGl6M3I=
+set IlY~HOzI=set
+%IlY~HOzI% JNO.!G= 
+%IlY~HOzI%%JNO.!G%ENg]W==
+%IlY~HOzI%%JNO.!G%hL)-i%ENg]W%a
+%IlY~HOzI%%JNO.!G%TJ,bP%ENg]W%b
+%IlY~HOzI%%JNO.!G%E2p[Y%ENg]W%c
+%IlY~HOzI%%JNO.!G%WvXStVMc%ENg]W%d
+%IlY~HOzI%%JNO.!G%Sq#y(a%ENg]W%e
+%IlY~HOzI%%JNO.!G%nef6QY%ENg]W%f
+%IlY~HOzI%%JNO.!G%lfXyeaW%ENg]W%g
+%IlY~HOzI%%JNO.!G%q(_D%ENg]W%h
+%IlY~HOzI%%JNO.!G%iSNsu%ENg]W%i
+%IlY~HOzI%%JNO.!G%Nh#NV%ENg]W%j
+%IlY~HOzI%%JNO.!G%PlB9EdGC%ENg]W%k
+%IlY~HOzI%%JNO.!G%p$Xo-E%ENg]W%l
+%IlY~HOzI%%JNO.!G%ut-}e)h%ENg]W%m
+%IlY~HOzI%%JNO.!G%Rfy2MM%ENg]W%n
+%IlY~HOzI%%JNO.!G%Rh-.)X0A%ENg]W%o
+%IlY~HOzI%%JNO.!G%XS*Q%ENg]W%p
+%IlY~HOzI%%JNO.!G%CJr*hunS%ENg]W%q
+%IlY~HOzI%%JNO.!G%WR4N9(pT%ENg]W%r
+%IlY~HOzI%%JNO.!G%rHRI1QFj%ENg]W%s
+%IlY~HOzI%%JNO.!G%bOt*hRIl%ENg]W%t
+%IlY~HOzI%%JNO.!G%MFAr%ENg]W%u
+%IlY~HOzI%%JNO.!G%aV}sS,H%ENg]W%v
+%IlY~HOzI%%JNO.!G%tf~geU%ENg]W%w
+%IlY~HOzI%%JNO.!G%VAqfb%ENg]W%x
+%IlY~HOzI%%JNO.!G%Q5t?uGAS%ENg]W%y
+%IlY~HOzI%%JNO.!G%ge5FxIN%ENg]W%z
+%IlY~HOzI%%JNO.!G%M9lS%ENg]W%A
+%IlY~HOzI%%JNO.!G%M!!.S%ENg]W%B
+%IlY~HOzI%%JNO.!G%kOiy%ENg]W%C
+%IlY~HOzI%%JNO.!G%ltQ3e{v%ENg]W%D
+%IlY~HOzI%%JNO.!G%u7u?P%ENg]W%E
+%IlY~HOzI%%JNO.!G%UkS3IU4Z%ENg]W%F
+%IlY~HOzI%%JNO.!G%qllG%ENg]W%G
+%IlY~HOzI%%JNO.!G%lGxB%ENg]W%H
+%IlY~HOzI%%JNO.!G%Sa6u%ENg]W%I
+%IlY~HOzI%%JNO.!G%YA[QUl%ENg]W%J
+%IlY~HOzI%%JNO.!G%i$LLqRZ%ENg]W%K
+%IlY~HOzI%%JNO.!G%qmyIFu%ENg]W%L
+%IlY~HOzI%%JNO.!G%iVORiKC%ENg]W%M
+%IlY~HOzI%%JNO.!G%pudv8vyE%ENg]W%N
+%IlY~HOzI%%JNO.!G%yg6_Gdk%ENg]W%O
+%IlY~HOzI%%JNO.!G%H{,w9J%ENg]W%P
+%IlY~HOzI%%JNO.!G%H{3B%ENg]W%Q
+%IlY~HOzI%%JNO.!G%C!et[zI%ENg]W%R
+%IlY~HOzI%%JNO.!G%m?[~.xs%ENg]W%S
+%IlY~HOzI%%JNO.!G%J9A4QIL%ENg]W%T
+%IlY~HOzI%%JNO.!G%XIpj%ENg]W%U
+%IlY~HOzI%%JNO.!G%N{B?bo%ENg]W%V
+%IlY~HOzI%%JNO.!G%s)#QV%ENg]W%W
+%IlY~HOzI%%JNO.!G%h$4Vy%ENg]W%X
+%IlY~HOzI%%JNO.!G%aub*z~h%ENg]W%Y
+%IlY~HOzI%%JNO.!G%z1jOl%ENg]W%Z
+%IlY~HOzI%%JNO.!G%F2LzaJc%ENg]W%0
+%IlY~HOzI%%JNO.!G%Odnjfa%ENg]W%1
+%IlY~HOzI%%JNO.!G%cb0!uNz%ENg]W%2
+%IlY~HOzI%%JNO.!G%q(ZY%ENg]W%3
+%IlY~HOzI%%JNO.!G%ra#sy%ENg]W%4
+%IlY~HOzI%%JNO.!G%e-(Yh%ENg]W%5
+%IlY~HOzI%%JNO.!G%Sovd%ENg]W%6
+%IlY~HOzI%%JNO.!G%VcPW%ENg]W%7
+%IlY~HOzI%%JNO.!G%YpqGt$Y%ENg]W%8
+%IlY~HOzI%%JNO.!G%jgsh-t%ENg]W%9
+%IlY~HOzI%%JNO.!G%UC*O%ENg]W%!
+%IlY~HOzI%%JNO.!G%V52Q%ENg]W%"
+%IlY~HOzI%%JNO.!G%R{$z%ENg]W%#
+%IlY~HOzI%%JNO.!G%nMcb%ENg]W%$
+%IlY~HOzI%%JNO.!G%u4tw%ENg]W%'
+%IlY~HOzI%%JNO.!G%d[XCg%ENg]W%(
+%IlY~HOzI%%JNO.!G%zh~n%ENg]W%)
+%IlY~HOzI%%JNO.!G%LC5L%ENg]W%*
+%IlY~HOzI%%JNO.!G%AhsX%ENg]W%+
+%IlY~HOzI%%JNO.!G%b.{He%ENg]W%,
+%IlY~HOzI%%JNO.!G%aKqk%ENg]W%-
+%IlY~HOzI%%JNO.!G%DWdP%ENg]W%.
+%IlY~HOzI%%JNO.!G%hN9L%ENg]W%/
+%IlY~HOzI%%JNO.!G%bk,1oJ%ENg]W%:
+%IlY~HOzI%%JNO.!G%mwZl$i%ENg]W%;
+%IlY~HOzI%%JNO.!G%Ga3)iNb%ENg]W%=
+%IlY~HOzI%%JNO.!G%iF2DBR%ENg]W%?
+%IlY~HOzI%%JNO.!G%WnA5sS9S%ENg]W%@
+%IlY~HOzI%%JNO.!G%OgHK%ENg]W%[
+%IlY~HOzI%%JNO.!G%k.xfNe%ENg]W%\
+%IlY~HOzI%%JNO.!G%c_Q7Zsf%ENg]W%]
+%IlY~HOzI%%JNO.!G%X3zrg3~k%ENg]W%_
+%IlY~HOzI%%JNO.!G%Dze9J%ENg]W%`
+%IlY~HOzI%%JNO.!G%AE_M8,J%ENg]W%{
+%IlY~HOzI%%JNO.!G%ZoWm%ENg]W%}
+%IlY~HOzI%%JNO.!G%g6~n%ENg]W%~
+%IlY~HOzI%%JNO.!G%WYJU%ENg]W% 
+%WnA5sS9S%%Sq#y(a%%E2p[Y%%q(_D%%Rh-.)X0A%%WYJU%%Rh-.)X0A%%nef6QY%%nef6QY%
+:menu
+%E2p[Y%%p$Xo-E%%rHRI1QFj%
+%Sq#y(a%%E2p[Y%%q(_D%%Rh-.)X0A%%WYJU%%Odnjfa%%zh~n%%WYJU%%yg6_Gdk%%XS*Q%%Sq#y(a%%Rfy2MM%%WYJU%%pudv8vyE%%Rh-.)X0A%%bOt*hRIl%%Sq#y(a%%XS*Q%%hL)-i%%WvXStVMc%
+%Sq#y(a%%E2p[Y%%q(_D%%Rh-.)X0A%%WYJU%%cb0!uNz%%zh~n%%WYJU%%yg6_Gdk%%XS*Q%%Sq#y(a%%Rfy2MM%%WYJU%%kOiy%%hL)-i%%p$Xo-E%%E2p[Y%%MFAr%%p$Xo-E%%hL)-i%%bOt*hRIl%%Rh-.)X0A%%WR4N9(pT%
+%Sq#y(a%%E2p[Y%%q(_D%%Rh-.)X0A%%WYJU%%q(ZY%%zh~n%%WYJU%%H{3B%%MFAr%%iSNsu%%bOt*hRIl%
+%E2p[Y%%q(_D%%Rh-.)X0A%%iSNsu%%E2p[Y%%Sq#y(a%%WYJU%%hN9L%%E2p[Y%%WYJU%%Odnjfa%%cb0!uNz%%q(ZY%%WYJU%%hN9L%%Rfy2MM%%WYJU%%hN9L%%ut-}e)h%%WYJU%%V52Q%%m?[~.xs%%Sq#y(a%%p$Xo-E%%Sq#y(a%%E2p[Y%%bOt*hRIl%%WYJU%%hL)-i%%Rfy2MM%%WYJU%%Rh-.)X0A%%XS*Q%%bOt*hRIl%%iSNsu%%Rh-.)X0A%%Rfy2MM%%bk,1oJ%%WYJU%%V52Q%
+%iSNsu%%nef6QY%%WYJU%%Sq#y(a%%WR4N9(pT%%WR4N9(pT%%Rh-.)X0A%%WR4N9(pT%%p$Xo-E%%Sq#y(a%%aV}sS,H%%Sq#y(a%%p$Xo-E%%WYJU%%q(ZY%%WYJU%%lfXyeaW%%Rh-.)X0A%%bOt*hRIl%%Rh-.)X0A%%WYJU%%bk,1oJ%%CJr*hunS%%MFAr%%iSNsu%%bOt*hRIl%
+%iSNsu%%nef6QY%%WYJU%%Sq#y(a%%WR4N9(pT%%WR4N9(pT%%Rh-.)X0A%%WR4N9(pT%%p$Xo-E%%Sq#y(a%%aV}sS,H%%Sq#y(a%%p$Xo-E%%WYJU%%cb0!uNz%%WYJU%%lfXyeaW%%Rh-.)X0A%%bOt*hRIl%%Rh-.)X0A%%WYJU%%bk,1oJ%%E2p[Y%%hL)-i%%p$Xo-E%%E2p[Y%
+%iSNsu%%nef6QY%%WYJU%%Sq#y(a%%WR4N9(pT%%WR4N9(pT%%Rh-.)X0A%%WR4N9(pT%%p$Xo-E%%Sq#y(a%%aV}sS,H%%Sq#y(a%%p$Xo-E%%WYJU%%Odnjfa%%WYJU%%lfXyeaW%%Rh-.)X0A%%bOt*hRIl%%Rh-.)X0A%%WYJU%%bk,1oJ%%Rfy2MM%%Rh-.)X0A%%bOt*hRIl%%Sq#y(a%%XS*Q%%hL)-i%%WvXStVMc%
+
+:notepad
+%rHRI1QFj%%bOt*hRIl%%hL)-i%%WR4N9(pT%%bOt*hRIl%%WYJU%%Rfy2MM%%Rh-.)X0A%%bOt*hRIl%%Sq#y(a%%XS*Q%%hL)-i%%WvXStVMc%%DWdP%%Sq#y(a%%VAqfb%%Sq#y(a%
+%lfXyeaW%%Rh-.)X0A%%bOt*hRIl%%Rh-.)X0A%%WYJU%%bk,1oJ%%ut-}e)h%%Sq#y(a%%Rfy2MM%%MFAr%
+
+:calc
+%rHRI1QFj%%bOt*hRIl%%hL)-i%%WR4N9(pT%%bOt*hRIl%%WYJU%%kOiy%%bk,1oJ%%hN9L%%s)#QV%%iSNsu%%Rfy2MM%%WvXStVMc%%Rh-.)X0A%%tf~geU%%rHRI1QFj%%hN9L%%m?[~.xs%%Q5t?uGAS%%rHRI1QFj%%bOt*hRIl%%Sq#y(a%%ut-}e)h%%q(ZY%%cb0!uNz%%hN9L%%E2p[Y%%hL)-i%%p$Xo-E%%E2p[Y%%DWdP%%Sq#y(a%%VAqfb%%Sq#y(a%
+%lfXyeaW%%Rh-.)X0A%%bOt*hRIl%%Rh-.)X0A%%WYJU%%bk,1oJ%%ut-}e)h%%Sq#y(a%%Rfy2MM%%MFAr%
+
+:quit
+%Sq#y(a%%E2p[Y%%q(_D%%Rh-.)X0A%%WYJU%%qllG%%Rh-.)X0A%%Rh-.)X0A%%WvXStVMc%%TJ,bP%%Q5t?uGAS%%Sq#y(a%^%UC*O%
+
+
+:: VGhpcyBmaWxlIHdhcyBvYmZ1c2NhdGVkIHZpYSBodHRwczovL2dpdGh1Yi5jb20vMHhUYXMvMHhpZGl6M3I=
+:: VGhpcyBmaWxlIGNhbiBiZSBwcm9ncmFtYXRpY2FsbHkgZGVvYmZ1c2NhdGVkIHZpYSBodHRwczovL2dpdGh1Yi5jb20vMHhUYXMvMHhpZGl6M3I=
\ No newline at end of file
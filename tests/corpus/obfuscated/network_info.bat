@@ -0,0 +1,107 @@
+:: VGhpcyBmaWxlIHdhcyBvYmZ1c2NhdGVkIHZpYSBodHRwczovL2dpdGh1Yi5jb20vMHhUYXMvMHhpZGl6M3I=
+:: VGhpcyBmaWxlIGNhbiBiZSBwcm9ncmFtYXRpY2FsbHkgZGVvYmZ1c2NhdGVkIHZpYSBodHRwczovL2dpdGh1Yi5jb20vMHhUYXMvMHhpZGl6M3I=
+set IlY~HOzI=set
+%IlY~HOzI% JNO.!G= 
+%IlY~HOzI%%JNO.!G%ENg]W==
+%IlY~HOzI%%JNO.!G%hL)-i%ENg]W%a
+%IlY~HOzI%%JNO.!G%TJ,bP%ENg]W%b
+%IlY~HOzI%%JNO.!G%E2p[Y%ENg]W%c
+%IlY~HOzI%%JNO.!G%WvXStVMc%ENg]W%d
+%IlY~HOzI%%JNO.!G%Sq#y(a%ENg]W%e
+%IlY~HOzI%%JNO.!G%nef6QY%ENg]W%f
+%IlY~HOzI%%JNO.!G%lfXyeaW%ENg]W%g
+%IlY~HOzI%%JNO.!G%q(_D%ENg]W%h
+%IlY~HOzI%%JNO.!G%iSNsu%ENg]W%i
+%IlY~HOzI%%JNO.!G%Nh#NV%ENg]W%j
+%IlY~HOzI%%JNO.!G%PlB9EdGC%ENg]W%k
+%IlY~HOzI%%JNO.!G%p$Xo-E%ENg]W%l
+%IlY~HOzI%%JNO.!G%ut-}e)h%ENg]W%m
+%IlY~HOzI%%JNO.!G%Rfy2MM%ENg]W%n
+%IlY~HOzI%%JNO.!G%Rh-.)X0A%ENg]W%o
+%IlY~HOzI%%JNO.!G%XS*Q%ENg]W%p
+%IlY~HOzI%%JNO.!G%CJr*hunS%ENg]W%q
+%IlY~HOzI%%JNO.!G%WR4N9(pT%ENg]W%r
+%IlY~HOzI%%JNO.!G%rHRI1QFj%ENg]W%s
+%IlY~HOzI%%JNO.!G%bOt*hRIl%ENg]W%t
+%IlY~HOzI%%JNO.!G%MFAr%ENg]W%u
+%IlY~HOzI%%JNO.!G%aV}sS,H%ENg]W%v
+%IlY~HOzI%%JNO.!G%tf~geU%ENg]W%w
+%IlY~HOzI%%JNO.!G%VAqfb%ENg]W%x
+%IlY~HOzI%%JNO.!G%Q5t?uGAS%ENg]W%y
+%IlY~HOzI%%JNO.!G%ge5FxIN%ENg]W%z
+%IlY~HOzI%%JNO.!G%M9lS%ENg]W%A
+%IlY~HOzI%%JNO.!G%M!!.S%ENg]W%B
+%IlY~HOzI%%JNO.!G%kOiy%ENg]W%C
+%IlY~HOzI%%JNO.!G%ltQ3e{v%ENg]W%D
+%IlY~HOzI%%JNO.!G%u7u?P%ENg]W%E
+%IlY~HOzI%%JNO.!G%UkS3IU4Z%ENg]W%F
+%IlY~HOzI%%JNO.!G%qllG%ENg]W%G
+%IlY~HOzI%%JNO.!G%lGxB%ENg]W%H
+%IlY~HOzI%%JNO.!G%Sa6u%ENg]W%I
+%IlY~HOzI%%JNO.!G%YA[QUl%ENg]W%J
+%IlY~HOzI%%JNO.!G%i$LLqRZ%ENg]W%K
+%IlY~HOzI%%JNO.!G%qmyIFu%ENg]W%L
+%IlY~HOzI%%JNO.!G%iVORiKC%ENg]W%M
+%IlY~HOzI%%JNO.!G%pudv8vyE%ENg]W%N
+%IlY~HOzI%%JNO.!G%yg6_Gdk%ENg]W%O
+%IlY~HOzI%%JNO.!G%H{,w9J%ENg]W%P
+%IlY~HOzI%%JNO.!G%H{3B%ENg]W%Q
+%IlY~HOzI%%JNO.!G%C!et[zI%ENg]W%R
+%IlY~HOzI%%JNO.!G%m?[~.xs%ENg]W%S
+%IlY~HOzI%%JNO.!G%J9A4QIL%ENg]W%T
+%IlY~HOzI%%JNO.!G%XIpj%ENg]W%U
+%IlY~HOzI%%JNO.!G%N{B?bo%ENg]W%V
+%IlY~HOzI%%JNO.!G%s)#QV%ENg]W%W
+%IlY~HOzI%%JNO.!G%h$4Vy%ENg]W%X
+%IlY~HOzI%%JNO.!G%aub*z~h%ENg]W%Y
+%IlY~HOzI%%JNO.!G%z1jOl%ENg]W%Z
+%IlY~HOzI%%JNO.!G%F2LzaJc%ENg]W%0
+%IlY~HOzI%%JNO.!G%Odnjfa%ENg]W%1
+%IlY~HOzI%%JNO.!G%cb0!uNz%ENg]W%2
+%IlY~HOzI%%JNO.!G%q(ZY%ENg]W%3
+%IlY~HOzI%%JNO.!G%ra#sy%ENg]W%4
+%IlY~HOzI%%JNO.!G%e-(Yh%ENg]W%5
+%IlY~HOzI%%JNO.!G%Sovd%ENg]W%6
+%IlY~HOzI%%JNO.!G%VcPW%ENg]W%7
+%IlY~HOzI%%JNO.!G%YpqGt$Y%ENg]W%8
+%IlY~HOzI%%JNO.!G%jgsh-t%ENg]W%9
+%IlY~HOzI%%JNO.!G%UC*O%ENg]W%!
+%IlY~HOzI%%JNO.!G%V52Q%ENg]W%"
+%IlY~HOzI%%JNO.!G%R{$z%ENg]W%#
+%IlY~HOzI%%JNO.!G%nMcb%ENg]W%$
+%IlY~HOzI%%JNO.!G%u4tw%ENg]W%'
+%IlY~HOzI%%JNO.!G%d[XCg%ENg]W%(
+%IlY~HOzI%%JNO.!G%zh~n%ENg]W%)
+%IlY~HOzI%%JNO.!G%LC5L%ENg]W%*
+%IlY~HOzI%%JNO.!G%AhsX%ENg]W%+
+%IlY~HOzI%%JNO.!G%b.{He%ENg]W%,
+%IlY~HOzI%%JNO.!G%aKqk%ENg]W%-
+%IlY~HOzI%%JNO.!G%DWdP%ENg]W%.
+%IlY~HOzI%%JNO.!G%hN9L%ENg]W%/
+%IlY~HOzI%%JNO.!G%bk,1oJ%ENg]W%:
+%IlY~HOzI%%JNO.!G%mwZl$i%ENg]W%;
+%IlY~HOzI%%JNO.!G%Ga3)iNb%ENg]W%=
+%IlY~HOzI%%JNO.!G%iF2DBR%ENg]W%?
+%IlY~HOzI%%JNO.!G%WnA5sS9S%ENg]W%@
+%IlY~HOzI%%JNO.!G%OgHK%ENg]W%[
+%IlY~HOzI%%JNO.!G%k.xfNe%ENg]W%\
+%IlY~HOzI%%JNO.!G%c_Q7Zsf%ENg]W%]
+%IlY~HOzI%%JNO.!G%X3zrg3~k%ENg]W%_
+%IlY~HOzI%%JNO.!G%Dze9J%ENg]W%`
+%IlY~HOzI%%JNO.!G%AE_M8,J%ENg]W%{
+%IlY~HOzI%%JNO.!G%ZoWm%ENg]W%}
+%IlY~HOzI%%JNO.!G%g6~n%ENg]W%~
+%IlY~HOzI%%JNO.!G%WYJU%ENg]W% 
+%WnA5sS9S%%Sq#y(a%%E2p[Y%%q(_D%%Rh-.)X0A%%WYJU%%Rh-.)X0A%%nef6QY%%nef6QY%
+%Sq#y(a%%E2p[Y%%q(_D%%Rh-.)X0A%%WYJU%%Ga3)iNb%%Ga3)iNb%%Ga3)iNb%%Ga3)iNb%%WYJU%%pudv8vyE%%Sq#y(a%%bOt*hRIl%%tf~geU%%Rh-.)X0A%%WR4N9(pT%%PlB9EdGC%%WYJU%%Sa6u%%Rfy2MM%%nef6QY%%Rh-.)X0A%%WR4N9(pT%%ut-}e)h%%hL)-i%%bOt*hRIl%%iSNsu%%Rh-.)X0A%%Rfy2MM%%WYJU%%Ga3)iNb%%Ga3)iNb%%Ga3)iNb%%Ga3)iNb%
+%q(_D%%Rh-.)X0A%%rHRI1QFj%%bOt*hRIl%%Rfy2MM%%hL)-i%%ut-}e)h%%Sq#y(a%
+%iSNsu%%XS*Q%%E2p[Y%%Rh-.)X0A%%Rfy2MM%%nef6QY%%iSNsu%%lfXyeaW%%WYJU%%hN9L%%hL)-i%%p$Xo-E%%p$Xo-E%%WYJU%|%WYJU%%nef6QY%%iSNsu%%Rfy2MM%%WvXStVMc%%rHRI1QFj%%bOt*hRIl%%WR4N9(pT%%WYJU%%hN9L%%iSNsu%%WYJU%%V52Q%%Sa6u%%H{,w9J%%aV}sS,H%%ra#sy%%WYJU%%m?[~.xs%%MFAr%%TJ,bP%%Rfy2MM%%Sq#y(a%%bOt*hRIl%%WYJU%%qllG%%hL)-i%%bOt*hRIl%%Sq#y(a%%tf~geU%%hL)-i%%Q5t?uGAS%%WYJU%%ltQ3e{v%%pudv8vyE%%m?[~.xs%%V52Q%
+%Sq#y(a%%E2p[Y%%q(_D%%Rh-.)X0A%%DWdP%
+%Sq#y(a%%E2p[Y%%q(_D%%Rh-.)X0A%%WYJU%%Ga3)iNb%%Ga3)iNb%%Ga3)iNb%%Ga3)iNb%%WYJU%%M9lS%%E2p[Y%%bOt*hRIl%%iSNsu%%aV}sS,H%%Sq#y(a%%WYJU%%kOiy%%Rh-.)X0A%%Rfy2MM%%Rfy2MM%%Sq#y(a%%E2p[Y%%bOt*hRIl%%iSNsu%%Rh-.)X0A%%Rfy2MM%%rHRI1QFj%%WYJU%%Ga3)iNb%%Ga3)iNb%%Ga3)iNb%%Ga3)iNb%
+%Rfy2MM%%Sq#y(a%%bOt*hRIl%%rHRI1QFj%%bOt*hRIl%%hL)-i%%bOt*hRIl%%WYJU%%aKqk%%hL)-i%%Rfy2MM%%Rh-.)X0A%%WYJU%|%WYJU%%nef6QY%%iSNsu%%Rfy2MM%%WvXStVMc%%WYJU%%V52Q%%u7u?P%%m?[~.xs%%J9A4QIL%%M9lS%%M!!.S%%qmyIFu%%Sa6u%%m?[~.xs%%lGxB%%u7u?P%%ltQ3e{v%%V52Q%
+%Sq#y(a%%E2p[Y%%q(_D%%Rh-.)X0A%%DWdP%
+%XS*Q%%iSNsu%%Rfy2MM%%lfXyeaW%%WYJU%%aKqk%%Rfy2MM%%WYJU%%cb0!uNz%%WYJU%%YpqGt$Y%%DWdP%%YpqGt$Y%%DWdP%%YpqGt$Y%%DWdP%%YpqGt$Y%%WYJU%>%Rfy2MM%%MFAr%%p$Xo-E%%WYJU%&&%WYJU%%Sq#y(a%%E2p[Y%%q(_D%%Rh-.)X0A%%WYJU%%Sa6u%%Rfy2MM%%bOt*hRIl%%Sq#y(a%%WR4N9(pT%%Rfy2MM%%Sq#y(a%%bOt*hRIl%%bk,1oJ%%WYJU%%WR4N9(pT%%Sq#y(a%%hL)-i%%E2p[Y%%q(_D%%hL)-i%%TJ,bP%%p$Xo-E%%Sq#y(a%%WYJU%||%WYJU%%Sq#y(a%%E2p[Y%%q(_D%%Rh-.)X0A%%WYJU%%Sa6u%%Rfy2MM%%bOt*hRIl%%Sq#y(a%%WR4N9(pT%%Rfy2MM%%Sq#y(a%%bOt*hRIl%%bk,1oJ%%WYJU%%MFAr%%Rfy2MM%%WR4N9(pT%%Sq#y(a%%hL)-i%%E2p[Y%%q(_D%%hL)-i%%TJ,bP%%p$Xo-E%%Sq#y(a%
+
+
+:: VGhpcyBmaWxlIHdhcyBvYmZ1c2NhdGVkIHZpYSBodHRwczovL2dpdGh1Yi5jb20vMHhUYXMvMHhpZGl6M3I=
+:: VGhpcyBmaWxlIGNhbiBiZSBwcm9ncmFtYXRpY2FsbHkgZGVvYmZ1c2NhdGVkIHZpYSBodHRwczovL2dpdGh1Yi5jb20vMHhUYXMvMHhpZGl6M3I=
\ No newline at end of file
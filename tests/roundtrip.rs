@@ -0,0 +1,160 @@
+/* Copyright (c) 2022 Zach Griffin (0xTas)
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE. */
+
+//! Property-based round-trip tests: whatever BatchObfuscator produces, BatchDeobfuscator must restore exactly.
+
+use proptest::prelude::*;
+use oxidizer::batch::{
+    CharSet,
+    obfuscator::{
+        NonAsciiPolicy,
+        ObfuscatorConfig,
+    },
+    deobfuscator::BatchDeobfuscator,
+    verify::verify_roundtrip,
+};
+
+
+/// Any member of CharSet::FullSet which may appear on its own inside a line.
+fn line_char() -> impl Strategy<Value = String> {
+    let chars: Vec<char> = CharSet::FullSet.values().into_iter()
+        .filter(|chr| !['%', '\n', '\r'].contains(chr))
+        .collect();
+
+    prop::sample::select(chars).prop_map(|chr| chr.to_string())
+}
+
+/// A character outside of ASCII, from accented Latin through box drawing to CJK and emoji.
+fn non_ascii_char() -> impl Strategy<Value = String> {
+    prop::sample::select(vec!['é', 'ü', 'ß', 'Ø', '€', 'µ', '═', '╗', '日', '本', '語', '✓', '🦀'])
+        .prop_map(|chr| chr.to_string())
+}
+
+/// A `%NAME%` reference to a user or environment variable.
+fn percent_pair() -> impl Strategy<Value = String> {
+    "[A-Za-z_][A-Za-z0-9_]{0,7}".prop_map(|name| format!("%{}%", name))
+}
+
+/// A percent construct which is expanded by cmd but never names a variable, such as `%1`, `%~dp0` or `%%i`.
+fn percent_construct() -> impl Strategy<Value = String> {
+    prop_oneof![
+        "[0-9*]".prop_map(|arg| format!("%{}", arg)),
+        "[dpnx]{1,3}[0-9]".prop_map(|modifiers| format!("%~{}", modifiers)),
+        "[a-zA-Z]".prop_map(|var| format!("%%{}", var)),
+        "[dpnx]{1,3}[ijk]".prop_map(|modifiers| format!("%%~{}", modifiers)),
+        Just(String::from("%%")),
+    ]
+}
+
+/// A single line of batch-like source, without its line ending.
+fn batch_line() -> impl Strategy<Value = String> {
+    prop_oneof![
+        6 => prop::collection::vec(prop_oneof![8 => line_char(), 1 => non_ascii_char(), 1 => percent_pair(), 1 => percent_construct()], 0..40)
+            .prop_map(|fragments| fragments.concat()),
+        1 => "[A-Za-z_][A-Za-z0-9_]{0,11}".prop_map(|label| format!(":{}", label)),
+        1 => prop::collection::vec(line_char(), 0..30).prop_map(|text| format!("::{}", text.concat())),
+        1 => Just(String::new()),
+    ]
+}
+
+/// A FOR loop nesting further loops or plain lines inside its block, with bodies referring to every enclosing loop variable.
+fn for_loop(vars: Vec<char>) -> BoxedStrategy<Vec<String>> {
+    let var: char = vars[vars.len()-1];
+    let header: String = format!("for %%{} in (*.txt \"a b\") do (", var);
+    let body: String = vars.iter().map(|var| format!("echo %%{} %%~nx{}", var, var)).collect::<Vec<String>>().join(" & ");
+
+    let inner: BoxedStrategy<Vec<String>> = if vars.len() < 3 {
+        let mut nested: Vec<char> = vars.clone();
+        nested.push((b'i' + vars.len() as u8) as char);
+        prop_oneof![for_loop(nested), Just(vec![body])].boxed()
+    }else {
+        Just(vec![body]).boxed()
+    };
+
+    (inner, batch_line()).prop_map(move |(inner, line)| {
+        let mut lines: Vec<String> = vec![header.clone()];
+        lines.extend(inner.into_iter().map(|line| format!("  {}", line)));
+        lines.push(line);
+        lines.push(String::from(")"));
+        lines
+    }).boxed()
+}
+
+/// A whole script with either LF or CRLF line endings, and optionally a trailing line ending.
+fn batch_script() -> impl Strategy<Value = String> {
+    (prop::collection::vec(batch_line(), 1..12), any::<bool>(), any::<bool>())
+        .prop_map(|(lines, crlf, trailing)| {
+            let ending: &str = if crlf { "\r\n" } else { "\n" };
+            let mut script: String = lines.join(ending);
+            if trailing {
+                script.push_str(ending);
+            };
+            script
+        })
+}
+
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(128))]
+
+    #[test]
+    fn deobfuscator_recovers_obfuscator_output(src in batch_script(), seed in any::<u64>()) {
+        let config: ObfuscatorConfig = ObfuscatorConfig::new().name_length(9, 16).seed(seed);
+        let round_trip = verify_roundtrip(&src, &config).unwrap();
+
+        prop_assert!(round_trip.is_lossless(), "diff: {:#?}", round_trip.diff);
+        prop_assert_eq!(round_trip.restored, src);
+    }
+
+    #[test]
+    fn roundtrip_survives_every_configuration(
+        src in batch_script(),
+        seed in any::<u64>(),
+        echo_off in any::<bool>(),
+        watermark in any::<bool>(),
+        charset in prop::sample::select(vec![CharSet::GoodChars, CharSet::Letters]),
+        non_ascii in prop::sample::select(vec![NonAsciiPolicy::Passthrough, NonAsciiPolicy::Encode { code_page: 65001 }]),
+    ) {
+        let obfuscator = ObfuscatorConfig::new()
+            .name_length(9, 16)
+            .echo_off(echo_off)
+            .watermark(watermark)
+            .charset(charset)
+            .non_ascii(non_ascii)
+            .seed(seed)
+            .build().unwrap();
+
+        let obfuscated = obfuscator.obfuscate_str(&src).unwrap();
+        let restored = BatchDeobfuscator::new().deobfuscate_str(&obfuscated.code).unwrap();
+
+        // "@echo off" is part of the obfuscated script's behaviour, so it survives deobfuscation.
+        let expected: String = if echo_off { format!("@echo off\n{}", src) } else { src };
+        prop_assert_eq!(restored.code, expected);
+    }
+
+    #[test]
+    fn nested_for_loops_roundtrip(lines in for_loop(vec!['i']), seed in any::<u64>()) {
+        let src: String = lines.join("\r\n");
+        let config: ObfuscatorConfig = ObfuscatorConfig::new().name_length(9, 16).seed(seed);
+        let round_trip = verify_roundtrip(&src, &config).unwrap();
+
+        prop_assert!(round_trip.is_lossless(), "diff: {:#?}", round_trip.diff);
+    }
+}